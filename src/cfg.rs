@@ -0,0 +1,227 @@
+//! Evaluating the `cfg(...)` expressions crates can use as their
+//! [`target`](crate::Crate::Detailed::target), like `cfg(windows)` or
+//! `cfg(all(unix, target_arch = "aarch64"))`.
+//!
+//! A crate with a `cfg(...)` target is only vendored for a toolchain if the
+//! expression holds for at least one of the toolchain's platforms or targets,
+//! going by what `rustc --print cfg --target <target>` prints for them.
+
+use std::{collections::HashSet, fmt, process::Command, str::FromStr};
+
+use anyhow::{anyhow, Error};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A parsed `cfg(...)` expression.
+pub enum CfgExpr {
+    /// A name on its own, like `unix`.
+    Name(String),
+    /// A key and a value, like `target_os = "linux"`.
+    KeyValue(String, String),
+    /// `all(...)`, which holds if every expression does(or if it's empty).
+    All(Vec<CfgExpr>),
+    /// `any(...)`, which holds if any expression does.
+    Any(Vec<CfgExpr>),
+    /// `not(...)`.
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    /// Whether the expression holds for a target with the cfg values `cfg`.
+    pub fn matches(&self, cfg: &TargetCfg) -> bool {
+        match self {
+            CfgExpr::Name(name) => cfg.names.contains(name),
+            CfgExpr::KeyValue(key, value) => cfg.values.contains(&(key.clone(), value.clone())),
+            CfgExpr::All(exprs) => exprs.iter().all(|expr| expr.matches(cfg)),
+            CfgExpr::Any(exprs) => exprs.iter().any(|expr| expr.matches(cfg)),
+            CfgExpr::Not(expr) => !expr.matches(cfg),
+        }
+    }
+}
+
+impl FromStr for CfgExpr {
+    type Err = Error;
+    /// Parses a whole `cfg(...)`, not just what's inside it.
+    fn from_str(s: &str) -> Result<CfgExpr, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        parser.expect(Token::Ident("cfg".to_string()))?;
+        parser.expect(Token::Open)?;
+        let expr = parser.expr()?;
+        parser.expect(Token::Close)?;
+        if parser.pos != parser.tokens.len() {
+            return Err(anyhow!("unexpected input after the end of `{}`", s));
+        }
+        Ok(expr)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The cfg values that are set for a target.
+pub struct TargetCfg {
+    names: HashSet<String>,
+    values: HashSet<(String, String)>,
+}
+
+impl FromStr for TargetCfg {
+    type Err = Error;
+    /// Parses the output of `rustc --print cfg`, which has one `name` or
+    /// `key="value"` per line.
+    fn from_str(s: &str) -> Result<TargetCfg, Error> {
+        let mut cfg = TargetCfg::default();
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|value| value.strip_suffix('"'))
+                        .ok_or_else(|| anyhow!("invalid cfg `{}`", line))?;
+                    cfg.values.insert((key.to_string(), value.to_string()));
+                }
+                None => {
+                    cfg.names.insert(line.to_string());
+                }
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+/// Asks `rustc` for the cfg values of `target`.
+pub fn rustc_cfg(target: &str) -> Result<TargetCfg, Error> {
+    let output = Command::new("rustc")
+        .args(["--print", "cfg", "--target", target])
+        .output()
+        .map_err(|err| anyhow!("failed to run rustc: {}", err))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "rustc couldn't print the cfg of {}: {}",
+            target,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout).parse()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Open,
+    Close,
+    Comma,
+    Equals,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Str(value) => write!(f, "`\"{}\"`", value),
+            Token::Open => f.write_str("`(`"),
+            Token::Close => f.write_str("`)`"),
+            Token::Comma => f.write_str("`,`"),
+            Token::Equals => f.write_str("`=`"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ',' => tokens.push(Token::Comma),
+            '=' => tokens.push(Token::Equals),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(anyhow!("unterminated string in `{}`", s)),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(anyhow!("unexpected `{}` in `{}`", c, s)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), Error> {
+        match self.next() {
+            Some(next) if next == token => Ok(()),
+            Some(next) => Err(anyhow!("expected {}, found {}", token, next)),
+            None => Err(anyhow!("expected {}, found the end", token)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<CfgExpr, Error> {
+        let Some(Token::Ident(name)) = self.next() else {
+            return Err(anyhow!("expected a cfg name"));
+        };
+        match self.peek() {
+            Some(Token::Equals) => {
+                self.pos += 1;
+                match self.next() {
+                    Some(Token::Str(value)) => Ok(CfgExpr::KeyValue(name, value)),
+                    _ => Err(anyhow!("expected a string after `{} =`", name)),
+                }
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let mut exprs = Vec::new();
+                while self.peek() != Some(&Token::Close) {
+                    exprs.push(self.expr()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.pos += 1;
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::Close)?;
+                match name.as_str() {
+                    "all" => Ok(CfgExpr::All(exprs)),
+                    "any" => Ok(CfgExpr::Any(exprs)),
+                    "not" if exprs.len() == 1 => Ok(CfgExpr::Not(Box::new(exprs.remove(0)))),
+                    "not" => Err(anyhow!("`not` takes exactly one expression")),
+                    _ => Err(anyhow!("unknown cfg operator `{}`", name)),
+                }
+            }
+            _ => Ok(CfgExpr::Name(name)),
+        }
+    }
+}
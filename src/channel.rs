@@ -0,0 +1,424 @@
+//! Parsing and resolving rustup-style toolchain specs, like `stable`, `1.83.0`,
+//! `1.82` or `nightly-2024-11-30`, to the channel manifests served by
+//! [`DEFAULT_UPSTREAM_URL`](crate::copied::DEFAULT_UPSTREAM_URL).
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use chrono::NaiveDate;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The channel part of a [`ToolchainSpec`].
+pub enum Channel {
+    /// The stable channel.
+    Stable,
+    /// The beta channel.
+    Beta,
+    /// The nightly channel.
+    Nightly,
+    /// A specific release, either `major.minor` (the latest patch release) or
+    /// `major.minor.patch`.
+    Version(String),
+}
+
+impl Channel {
+    /// The name of the channel, as used in `channel-rust-{}.toml`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+            Channel::Version(version) => version,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A toolchain spec, as accepted by [`Toolchain::channel`](crate::Toolchain::channel).
+///
+/// This is the same format rustup uses(excluding the host triple, which is
+/// given by [`Toolchain::platforms`](crate::Toolchain::platforms) instead):
+/// `<channel>[-<date>]`, where the channel is one of [`RELEASE_CHANNELS`](crate::targets::RELEASE_CHANNELS) or
+/// a version number, and the date(`YYYY-MM-DD`) is only allowed for named channels.
+pub struct ToolchainSpec {
+    /// The channel.
+    pub channel: Channel,
+    /// The date of the release, if one was given.
+    pub date: Option<NaiveDate>,
+}
+
+impl ToolchainSpec {
+    /// The path of the channel manifest relative to the upstream URL, for
+    /// example `dist/channel-rust-1.83.0.toml` or
+    /// `dist/2024-11-30/channel-rust-nightly.toml`.
+    pub fn manifest_path(&self) -> String {
+        match self.date {
+            Some(date) => format!(
+                "dist/{}/channel-rust-{}.toml",
+                date.format("%Y-%m-%d"),
+                self.channel.as_str()
+            ),
+            None => format!("dist/channel-rust-{}.toml", self.channel.as_str()),
+        }
+    }
+}
+
+impl FromStr for ToolchainSpec {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<ToolchainSpec, Error> {
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            let parts: Vec<&str> = s.split('.').collect();
+            if !(2..=3).contains(&parts.len())
+                || parts
+                    .iter()
+                    .any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()))
+            {
+                return Err(anyhow!("invalid version {}", s));
+            }
+            return Ok(ToolchainSpec {
+                channel: Channel::Version(s.to_string()),
+                date: None,
+            });
+        }
+
+        let (channel, date) = s.split_once('-').unwrap_or((s, ""));
+        let channel = match channel {
+            "stable" => Channel::Stable,
+            "beta" => Channel::Beta,
+            "nightly" => Channel::Nightly,
+            _ => return Err(anyhow!("invalid channel {}", s)),
+        };
+        let date = match date {
+            "" => None,
+            date => Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("invalid date {} in channel {}", date, s))?,
+            ),
+        };
+        Ok(ToolchainSpec { channel, date })
+    }
+}
+
+impl fmt::Display for ToolchainSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.date {
+            Some(date) => write!(f, "{}-{}", self.channel.as_str(), date.format("%Y-%m-%d")),
+            None => f.write_str(self.channel.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+/// What a [`ToolchainSpec`] resolved to, read from its channel manifest.
+/// Written to `toolchain-info.toml` in every package so that the exact
+/// compiler version is recorded.
+pub struct ResolvedToolchain {
+    /// The spec that was resolved, as written in the config.
+    pub spec: String,
+    /// The date of the release.
+    pub date: String,
+    /// The full version of rustc, for example `1.83.0 (90b35a623 2024-11-26)`.
+    pub version: String,
+    /// The channel used in the names of the release's files, for example
+    /// `nightly` in `rustc-nightly-x86_64-unknown-linux-gnu.tar.gz`. For
+    /// stable releases, this is the version number.
+    pub artifact_channel: String,
+}
+
+impl ResolvedToolchain {
+    /// Reads the date and version out of a channel manifest for `spec`.
+    pub fn from_manifest(
+        spec: &ToolchainSpec,
+        manifest: &toml::Value,
+    ) -> Result<ResolvedToolchain, Error> {
+        let date = manifest
+            .get("date")
+            .and_then(toml::Value::as_str)
+            .ok_or_else(|| anyhow!("manifest for {} has no date", spec))?;
+        let version = manifest
+            .get("pkg")
+            .and_then(|pkg| pkg.get("rust"))
+            .and_then(|rust| rust.get("version"))
+            .and_then(toml::Value::as_str)
+            .ok_or_else(|| anyhow!("manifest for {} has no rust version", spec))?;
+        let artifact_channel = match spec.channel {
+            Channel::Beta => "beta",
+            Channel::Nightly => "nightly",
+            Channel::Stable | Channel::Version(_) => {
+                version.split_whitespace().next().unwrap_or(version)
+            }
+        };
+        Ok(ResolvedToolchain {
+            spec: spec.to_string(),
+            date: date.to_string(),
+            version: version.to_string(),
+            artifact_channel: artifact_channel.to_string(),
+        })
+    }
+}
+
+/// Downloads and parses the channel manifest for `spec` from `upstream_url`,
+/// checking it against the `.sha256` next to it.
+pub fn fetch_manifest(upstream_url: &str, spec: &ToolchainSpec) -> Result<toml::Value, Error> {
+    let url = format!("{}{}", upstream_url, spec.manifest_path());
+    let get = |url: &str| -> Result<String, Error> {
+        let response = reqwest::blocking::get(url)?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "failed to download the manifest for {}: {}",
+                spec,
+                response.status()
+            ));
+        }
+        Ok(response.text()?)
+    };
+    let text = get(&url)?;
+    let expected = get(&format!("{}.sha256", url))?;
+    let expected = expected.split_whitespace().next().unwrap_or_default();
+    let sha256 = hex::encode(Sha256::digest(text.as_bytes()));
+    if sha256 != expected {
+        return Err(anyhow!(
+            "expected SHA256 of the manifest for {} to be {} but was {}",
+            spec,
+            expected,
+            sha256
+        ));
+    }
+    let manifest: toml::Value = text.parse()?;
+    if manifest
+        .get("manifest-version")
+        .and_then(toml::Value::as_str)
+        != Some("2")
+    {
+        return Err(anyhow!("manifest version of channel {} not 2", spec));
+    }
+    Ok(manifest)
+}
+
+/// Lists the targets `package`(like `rustc` or `rust-std`) is available for in
+/// a channel manifest, sorted. Packages available for every target(like
+/// `rust-src`) have no targets of their own, so this returns nothing for them.
+pub fn available_targets(manifest: &toml::Value, package: &str) -> Vec<String> {
+    let mut targets: Vec<String> = manifest
+        .get("pkg")
+        .and_then(|pkg| pkg.get(package))
+        .and_then(|pkg| pkg.get("target"))
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flatten()
+        .filter(|(target, info)| {
+            *target != "*" && info.get("available").and_then(toml::Value::as_bool) == Some(true)
+        })
+        .map(|(target, _)| target.clone())
+        .collect();
+    targets.sort();
+    targets
+}
+
+/// Lists the components in a channel manifest that are available for every
+/// one of `platforms`, sorted. They're listed by the names toolchains use for
+/// them in `components`(so `clippy` rather than `clippy-preview`), going by
+/// the manifest's `renames` backwards(see [`resolve_components`]).
+pub fn available_components(manifest: &toml::Value, platforms: &[String]) -> Vec<String> {
+    let Some(pkgs) = manifest.get("pkg").and_then(toml::Value::as_table) else {
+        return Vec::new();
+    };
+    let mut components: Vec<String> = pkgs
+        .iter()
+        // `rust` is every component together, not a component of its own
+        .filter(|(name, _)| *name != "rust")
+        .filter(|(_, pkg)| {
+            let available = |target: &str| {
+                pkg.get("target")
+                    .and_then(|targets| targets.get(target))
+                    .and_then(|target| target.get("available"))
+                    .and_then(toml::Value::as_bool)
+                    == Some(true)
+            };
+            available("*") || platforms.iter().all(|platform| available(platform))
+        })
+        .map(|(name, _)| component_name(manifest, name).to_string())
+        .collect();
+    components.sort();
+    components
+}
+
+/// The name toolchains use for the manifest package `package`: the one
+/// `renames` renames to it, if there is one.
+fn component_name<'a>(manifest: &'a toml::Value, package: &'a str) -> &'a str {
+    manifest
+        .get("renames")
+        .and_then(toml::Value::as_table)
+        .and_then(|renames| {
+            renames
+                .iter()
+                .find(|(_, rename)| rename.get("to").and_then(toml::Value::as_str) == Some(package))
+        })
+        .map_or(package, |(name, _)| name.as_str())
+}
+
+/// Lists the components available for each target in a channel manifest, both
+/// sorted. Components that aren't specific to a target(like `rust-src`) are
+/// listed under `*`.
+pub fn components_by_target(manifest: &toml::Value) -> BTreeMap<String, Vec<String>> {
+    let mut targets: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let pkgs = manifest.get("pkg").and_then(toml::Value::as_table);
+    for (name, pkg) in pkgs.into_iter().flatten() {
+        // `rust` is every component together, not a component of its own
+        if name == "rust" {
+            continue;
+        }
+        let pkg_targets = pkg.get("target").and_then(toml::Value::as_table);
+        for (target, info) in pkg_targets.into_iter().flatten() {
+            if info.get("available").and_then(toml::Value::as_bool) == Some(true) {
+                targets
+                    .entry(target.clone())
+                    .or_default()
+                    .push(name.clone());
+            }
+        }
+    }
+    for components in targets.values_mut() {
+        components.sort();
+    }
+    targets
+}
+
+/// The component profiles in channel manifests, from smallest to largest. A
+/// toolchain's [`profile`](crate::Toolchain::profile) has to be one of these.
+pub const PROFILES: [&str; 3] = ["minimal", "default", "complete"];
+
+/// The packages a toolchain gets from a channel manifest: the ones in
+/// `profile`(if it's set) and `components`, sorted and without duplicates.
+///
+/// Like rustup, `components` are renamed according to the manifest's
+/// `renames`(so `clippy` becomes `clippy-preview`, which is what the package
+/// is called in the manifest and its profiles), and the components of the
+/// profile that aren't available for any of `platforms` are skipped. These
+/// are only the packages to download; see [`install_components`] for the
+/// names the install scripts use on each platform.
+pub fn resolve_components(
+    manifest: &toml::Value,
+    profile: Option<&str>,
+    components: &[impl AsRef<str>],
+    platforms: &[impl AsRef<str>],
+) -> Result<Vec<String>, Error> {
+    let mut resolved = Vec::new();
+    if let Some(profile) = profile {
+        let profile_components = manifest
+            .get("profiles")
+            .and_then(|profiles| profiles.get(profile))
+            .and_then(toml::Value::as_array)
+            .ok_or_else(|| anyhow!("manifest has no `{}` profile", profile))?;
+        for component in profile_components.iter().filter_map(toml::Value::as_str) {
+            let available = |target: &str| {
+                manifest
+                    .get("pkg")
+                    .and_then(|pkg| pkg.get(component))
+                    .and_then(|pkg| pkg.get("target"))
+                    .and_then(|targets| targets.get(target))
+                    .and_then(|target| target.get("available"))
+                    .and_then(toml::Value::as_bool)
+                    == Some(true)
+            };
+            if available("*")
+                || platforms
+                    .iter()
+                    .any(|platform| available(platform.as_ref()))
+            {
+                resolved.push(component.to_string());
+            }
+        }
+    }
+    for component in components {
+        let component = component.as_ref();
+        let component = manifest
+            .get("renames")
+            .and_then(|renames| renames.get(component))
+            .and_then(|rename| rename.get("to"))
+            .and_then(toml::Value::as_str)
+            .unwrap_or(component);
+        resolved.push(component.to_string());
+    }
+    resolved.sort();
+    resolved.dedup();
+    Ok(resolved)
+}
+
+/// The components the msi and pkg installers contain, which aren't installed
+/// from tarballs on the platforms that [have an installer](has_installer).
+pub const INSTALLER_COMPONENTS: [&str; 4] = ["rustc", "cargo", "rustdoc", "rust-std"];
+
+/// Whether `platform` gets an installer(msi on windows, pkg on apple) for
+/// the [`INSTALLER_COMPONENTS`].
+pub fn has_installer(platform: &str) -> bool {
+    platform.contains("windows") || platform.contains("apple")
+}
+
+/// Whether [`download_all`](crate::copied::download_all) downloads the tarball
+/// of `component` for `target`, which is one of the targets in the manifest
+/// or `*`. The tarballs of every target in `platforms` and `targets` are,
+/// other than those of the [`INSTALLER_COMPONENTS`] for platforms that
+/// [have an installer](has_installer). `rust-std` is the exception, since
+/// it's needed to cross compile to that platform from the others.
+pub fn downloads_tarball(
+    component: &str,
+    target: &str,
+    platforms: &[impl AsRef<str>],
+    targets: &[impl AsRef<str>],
+) -> bool {
+    if target == "*" || targets.iter().any(|t| t.as_ref() == target) {
+        return true;
+    }
+    platforms.iter().any(|p| p.as_ref() == target)
+        && !(has_installer(target)
+            && component != "rust-std"
+            && INSTALLER_COMPONENTS.contains(&component))
+}
+
+/// The names the install scripts use for the `components` of each of
+/// `platforms`, where `components` are the [resolved](resolve_components)
+/// ones. Those are the names of the tarballs rather than of the packages in
+/// the manifest(`clippy` rather than `clippy-preview`), taken from the `url`
+/// of each package, which is `<name>-<artifact_channel>-<platform>.tar.gz`.
+///
+/// A platform only gets the components that are available for it, and not
+/// the [`INSTALLER_COMPONENTS`] if it [has an installer](has_installer).
+/// Components without a tarball for a single platform(like `rust-src`) can't
+/// be installed by the scripts, so they're left out too.
+pub fn install_components(
+    manifest: &toml::Value,
+    components: &[impl AsRef<str>],
+    platforms: &[impl AsRef<str>],
+    artifact_channel: &str,
+) -> BTreeMap<String, Vec<String>> {
+    let mut install = BTreeMap::new();
+    for platform in platforms {
+        let platform = platform.as_ref();
+        let suffix = format!("-{}-{}.tar.gz", artifact_channel, platform);
+        let names = components
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|component| {
+                !(has_installer(platform) && INSTALLER_COMPONENTS.contains(component))
+            })
+            .filter_map(|component| {
+                let target = manifest
+                    .get("pkg")?
+                    .get(component)?
+                    .get("target")?
+                    .get(platform)?;
+                if target.get("available")?.as_bool() != Some(true) {
+                    return None;
+                }
+                let url = target.get("url")?.as_str()?;
+                let file_name = url.rsplit('/').next()?;
+                Some(file_name.strip_suffix(&suffix)?.to_string())
+            })
+            .collect();
+        install.insert(platform.to_string(), names);
+    }
+    install
+}
@@ -16,7 +16,8 @@ use std::path::{Component, Path, PathBuf};
 use toml::Value;
 use url::Url;
 
-use crate::Suffix;
+use crate::channel::{available_targets, downloads_tarball, resolve_components, ToolchainSpec};
+use crate::{FormatKind, Suffix};
 
 /// The default upstream URL. Usually passed to [`download`] or [`download_all`]
 /// when you don't have a custom upstream url to use.
@@ -26,7 +27,7 @@ pub const DEFAULT_UPSTREAM_URL: &str = "https://static.rust-lang.org/";
 fn file_sha256(file_path: &Path) -> Option<String> {
     let file = Path::new(file_path);
     if file.exists() {
-        let buffer = FileBuffer::open(&file).unwrap();
+        let buffer = FileBuffer::open(file).unwrap();
         Some(hex::encode(Sha256::new().chain_update(buffer).finalize()))
     } else {
         None
@@ -39,7 +40,7 @@ fn download(upstream_url: &str, dir: &str, path: &str) -> Result<PathBuf, Error>
     let manifest = format!("{}{}", upstream_url, path);
     let mut response = reqwest::blocking::get(&manifest)?;
     let mirror = Path::new(dir);
-    let file_path = mirror.join(&path);
+    let file_path = mirror.join(path);
     create_dir_all(file_path.parent().unwrap())?;
     let mut dest = File::create(file_path)?;
 
@@ -95,34 +96,38 @@ pub fn normalize_path(path: &Path) -> PathBuf {
 /// I changed this one from the original crate a *lot*. This is based
 /// on part of the main function in the original crate with many more
 /// validations and miscellaneous changes.
+///
+/// `components` are added on top of the ones in `profile`, which is read
+/// from each channel's manifest(see [`resolve_components`]). Which of their
+/// tarballs are downloaded is decided by [`downloads_tarball`].
+///
+/// `manifests` holds channel manifests that were already downloaded, keyed
+/// by the channel as written in `channels`; any other channel's manifest is
+/// downloaded into `orig_path`.
+#[allow(clippy::too_many_arguments)]
 pub fn download_all(
     channels: Vec<&str>,
     upstream_url: &str,
     orig_path: &str,
     mut targets: Vec<&str>,
     to_path: &str,
-    mut components: Vec<&str>,
+    components: Vec<&str>,
+    profile: Option<&str>,
     platforms: Vec<&str>,
     quiet: bool,
     format_map: HashMap<&str, Vec<crate::Format>>,
+    manifests: &HashMap<String, Value>,
 ) -> Option<Error> {
+    let mut specs = Vec::new();
     for channel in channels.clone() {
-        if !crate::targets::RELEASE_CHANNELS.contains(&channel) {
-            return Some(anyhow!("invalid channel"));
-        }
-    }
-    for target in targets.clone() {
-        if !crate::targets::TARGETS.contains(&target) {
-            return Some(anyhow!("invalid rust target"));
+        match channel.parse::<ToolchainSpec>() {
+            Ok(spec) => specs.push(spec),
+            Err(err) => return Some(err),
         }
     }
     for target in platforms.clone() {
-        if !crate::targets::TARGETS.contains(&target) {
-            return Some(anyhow!("invalid compilation target"));
-        }
-        let idx = targets.binary_search(&target);
-        if idx.is_ok() {
-            targets.swap_remove(idx.unwrap());
+        if let Ok(idx) = targets.binary_search(&target) {
+            targets.swap_remove(idx);
         }
     }
     for (target, formats) in format_map.clone() {
@@ -131,10 +136,10 @@ pub fn download_all(
                 "target {target} that is not being built for in target map"
             ));
         }
-        if formats.len() < 1 {
+        if formats.is_empty() {
             return Some(anyhow!("format list is empty"));
         }
-        if formats[0].format == "msi" && !target.contains("windows") {
+        if formats[0].format == FormatKind::Msi && !target.contains("windows") {
             if formats[0].suffix == Suffix::Only {
                 return Some(anyhow!(
                     "target {target} is not windows but formats require msi"
@@ -144,7 +149,7 @@ pub fn download_all(
                 warn!("target {target} is not windows but formats want msi; continuing");
             }
         }
-        if formats[0].format == "pkg" && !target.contains("apple") {
+        if formats[0].format == FormatKind::Pkg && !target.contains("apple") {
             if formats[0].suffix == Suffix::Only {
                 return Some(anyhow!(
                     "target {target} is not apple but formats require pkg"
@@ -154,18 +159,6 @@ pub fn download_all(
                 warn!("target {target} is not apple but formats want pkg; continuing");
             }
         }
-        for format in formats {
-            if !vec![
-                String::from("msi"),
-                String::from("pkg"),
-                String::from("gz"),
-                String::from("xz"),
-            ]
-            .contains(&format.format)
-            {
-                return Some(anyhow!("invalid format {}", format.format));
-            }
-        }
     }
 
     let mut all_targets = HashSet::new();
@@ -174,32 +167,59 @@ pub fn download_all(
     let mut referenced = HashSet::new();
 
     // Fetch rust components
-    for channel in channels.iter() {
-        let name = format!("dist/channel-rust-{}.toml", channel);
-        let file_path = download(upstream_url, orig_path, &name).unwrap();
-        let sha256_name = format!("dist/channel-rust-{}.toml.sha256", channel);
-        let sha256_file_path = download(upstream_url, orig_path, &sha256_name).unwrap();
-
-        let mut file = File::open(file_path.clone()).unwrap();
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
-
-        let mut sha256_file = File::open(sha256_file_path.clone()).unwrap();
-        let mut sha256_data = String::new();
-        sha256_file.read_to_string(&mut sha256_data).unwrap();
-        let sha256 = file_sha256(file_path.as_path()).unwrap();
-        if sha256 != &sha256_data[..64] {
-            return Some(anyhow!(
-                "expected SHA256 of {name} to be {} but was {}",
-                &sha256_data[..64],
-                sha256
-            ));
-        }
+    for (spec, requested) in specs.iter().zip(&channels) {
+        let channel = spec.channel.as_str();
+        let name = spec.manifest_path();
+        let sha256_name = format!("{}.sha256", name);
+
+        // manifests that were already fetched (and checked) are reused
+        let mut value = match manifests.get(*requested) {
+            Some(value) => value.clone(),
+            None => {
+                let file_path = download(upstream_url, orig_path, &name).unwrap();
+                let sha256_file_path = download(upstream_url, orig_path, &sha256_name).unwrap();
+
+                let mut file = File::open(file_path.clone()).unwrap();
+                let mut data = String::new();
+                file.read_to_string(&mut data).unwrap();
+
+                let mut sha256_file = File::open(sha256_file_path.clone()).unwrap();
+                let mut sha256_data = String::new();
+                sha256_file.read_to_string(&mut sha256_data).unwrap();
+                let sha256 = file_sha256(file_path.as_path()).unwrap();
+                if sha256 != sha256_data[..64] {
+                    return Some(anyhow!(
+                        "expected SHA256 of {name} to be {} but was {}",
+                        &sha256_data[..64],
+                        sha256
+                    ));
+                }
 
-        let mut value = data.parse::<Value>().unwrap();
+                data.parse::<Value>().unwrap()
+            }
+        };
         if value["manifest-version"].as_str() != Some("2") {
             return Some(anyhow!("manifest version of channel {channel} not 2"));
         }
+        // the targets are checked against the ones the channel actually has,
+        // so new targets work without updating `targets::TARGETS`
+        let known = available_targets(&value, "rust-std");
+        for target in targets.iter().chain(&platforms) {
+            if !known.iter().any(|known| known == target) {
+                return Some(anyhow!(
+                    "target {target} isn't available in channel {channel}"
+                ));
+            }
+        }
+        let resolved = match resolve_components(&value, profile, &components, &platforms) {
+            Ok(resolved) => resolved,
+            Err(err) => return Some(err),
+        };
+        let mut components: Vec<&str> = resolved.iter().map(String::as_str).collect();
+        // targets need rust-std even if the toolchain itself doesn't
+        if !components.contains(&"rust-std") {
+            components.push("rust-std")
+        }
 
         for ele in platforms.clone() {
             if ele.contains("windows") {
@@ -245,22 +265,6 @@ pub fn download_all(
                         info!("Writing checksum for file {}", file_name);
                     }
                 }
-                let idx = components.binary_search(&"rustc");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"cargo");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"rustdoc");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"rust-std");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
             } else if ele.contains("apple") {
                 let artifacts = value["artifacts"]["installer-pkg"]["target"][ele][0]
                     .as_table_mut()
@@ -304,27 +308,8 @@ pub fn download_all(
                         info!("Writing checksum for file {}", file_name);
                     }
                 }
-                let idx = components.binary_search(&"rustc");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"cargo");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"rustdoc");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
-                let idx = components.binary_search(&"rust-std");
-                if idx.is_ok() {
-                    components.swap_remove(idx.unwrap());
-                }
             }
         }
-        if !components.contains(&"rust-std") {
-            components.push("rust-std")
-        }
 
         let pkgs = value["pkg"].as_table_mut().unwrap();
         let keys: Vec<String> = pkgs.keys().cloned().collect();
@@ -341,9 +326,7 @@ pub fn download_all(
                 // set available to false and do not download
                 // but we will keep this table in the toml, which is required for newer version of
                 // rustup
-                if !(platforms.contains(&target.as_str()) || *target == "*")
-                    && !targets.contains(&target.as_str())
-                {
+                if !downloads_tarball(&component, target, &platforms, &targets) {
                     *pkg_target.get_mut("available").unwrap() = toml::Value::Boolean(false);
                     continue;
                 }
@@ -358,15 +341,15 @@ pub fn download_all(
                                 && !format_map.clone()[target.as_str()]
                                     .clone()
                                     .into_iter()
-                                    .any(|v| v.format == "xz")
+                                    .any(|v| v.format == FormatKind::Xz)
                             {
                                 continue;
                             }
-                            if *prefix == ""
+                            if prefix.is_empty()
                                 && !format_map.clone()[target.as_str()]
                                     .clone()
                                     .into_iter()
-                                    .any(|v| v.format == "gz")
+                                    .any(|v| v.format == FormatKind::Gz)
                             {
                                 continue;
                             }
@@ -447,6 +430,10 @@ pub fn download_all(
         let date = value["date"].as_str().unwrap();
 
         let alt_name = format!("dist/{}/channel-rust-{}.toml", date, channel);
+        if alt_name == name {
+            // the spec was already dated, so the manifest is already where it should be
+            continue;
+        }
         let alt_path = Path::new(to_path).join(&alt_name);
         create_dir_all(alt_path.parent().unwrap()).unwrap();
         copy(path, alt_path).unwrap();
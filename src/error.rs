@@ -0,0 +1,282 @@
+//! Errors produced while loading a `rust-config.toml` file.
+//!
+//! [`ConfigError`] keeps the path, the span of the offending value and the
+//! text of the file around so that the CLI can show a snippet of the config
+//! with a caret under the mistake instead of a panic backtrace.
+
+use std::{fmt, ops::Range, path::PathBuf};
+
+#[derive(Debug)]
+/// An error in (or while reading) a `rust-config.toml` file.
+pub struct ConfigError {
+    /// The path of the file the error occurred in.
+    pub path: PathBuf,
+    /// The byte range in the file of the value that caused the error, if known.
+    pub span: Option<Range<usize>>,
+    /// The dotted path of the config key the error occurred at(for example
+    /// `pkg.formats.linux[0]`), if known.
+    pub key: Option<String>,
+    /// A human readable description of the error.
+    pub message: String,
+    /// The contents of the file, used to render a snippet. `None` if the file
+    /// couldn't be read in the first place.
+    pub source_text: Option<String>,
+    /// Other locations relevant to the error, like where a conflicting key was
+    /// first defined. Rendered after the error itself.
+    pub notes: Vec<ConfigError>,
+}
+
+impl ConfigError {
+    /// Creates a new error without a span or any source text.
+    pub fn new(path: impl Into<PathBuf>, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            path: path.into(),
+            span: None,
+            key: None,
+            message: message.into(),
+            source_text: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Converts a [`toml::de::Error`] produced while parsing `source_text`
+    /// (the contents of `path`) into a [`ConfigError`].
+    pub fn from_toml(
+        path: impl Into<PathBuf>,
+        source_text: &str,
+        err: toml::de::Error,
+    ) -> ConfigError {
+        ConfigError {
+            path: path.into(),
+            span: err.span(),
+            key: None,
+            message: err.message().trim_end().to_string(),
+            source_text: Some(source_text.to_string()),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Converts an error produced while deserializing the (already parsed and
+    /// [resolved](crate::inherit::resolve)) contents of `path` into a
+    /// [`ConfigError`], recording the key it occurred at. If the error doesn't
+    /// have a span of its own, the span of that key in `source_text` is used.
+    pub fn from_deserialize(
+        path: impl Into<PathBuf>,
+        source_text: &str,
+        err: serde_path_to_error::Error<toml::de::Error>,
+    ) -> ConfigError {
+        let key = path_to_key(err.path());
+        let mut out = ConfigError::from_toml(path, source_text, err.into_inner());
+        if out.span.is_none() {
+            out.span = find_span(source_text, &key);
+        }
+        if !key.is_empty() {
+            out.key = Some(format_key(&key));
+        }
+        out
+    }
+
+    /// Creates an error at `key` in `source_text`(the contents of `path`).
+    pub fn at(
+        path: impl Into<PathBuf>,
+        source_text: &str,
+        key: &[KeySegment],
+        message: impl Into<String>,
+    ) -> ConfigError {
+        ConfigError {
+            path: path.into(),
+            span: find_span(source_text, key),
+            key: (!key.is_empty()).then(|| format_key(key)),
+            message: message.into(),
+            source_text: Some(source_text.to_string()),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a [note](ConfigError::notes) to the error.
+    pub fn with_note(mut self, note: ConfigError) -> ConfigError {
+        self.notes.push(note);
+        self
+    }
+
+    /// The message, prefixed with the [`key`](ConfigError::key) if it's known.
+    fn full_message(&self) -> String {
+        match &self.key {
+            Some(key) => format!("{}: {}", key, self.message),
+            None => self.message.clone(),
+        }
+    }
+
+    /// The 1-indexed line and column(in characters) of the start of
+    /// [`span`](ConfigError::span), if both the span and the source text are known.
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        let text = self.source_text.as_ref()?;
+        let start = self.span.as_ref()?.start.min(text.len());
+        let before = &text[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let col = text[line_start..start].chars().count() + 1;
+        Some((line, col))
+    }
+
+    /// Renders the error as a multi-line diagnostic, including a snippet of
+    /// the offending line with carets under the span when it's known.
+    /// [Notes](ConfigError::notes) are rendered the same way after it.
+    ///
+    /// ```text
+    /// error: invalid type: integer `5`, expected a sequence
+    ///  --> rust-config.toml:2:14
+    ///   |
+    /// 2 | toolchains = 5
+    ///   |              ^
+    /// ```
+    pub fn render(&self) -> String {
+        let mut out = self.render_as("error");
+        for note in &self.notes {
+            out += "\n";
+            out += &note.render_as("note");
+        }
+        out
+    }
+
+    fn render_as(&self, level: &str) -> String {
+        let (Some((line, col)), Some(text), Some(span)) =
+            (self.line_col(), &self.source_text, &self.span)
+        else {
+            return format!(
+                "{level}: {}\n --> {}",
+                self.full_message(),
+                self.path.display()
+            );
+        };
+        let line_text = text.lines().nth(line - 1).unwrap_or("");
+        let start = span.start.min(text.len());
+        let end = span.end.clamp(start, text.len());
+        let width = text[start..end]
+            .lines()
+            .next()
+            .map(|s| s.chars().count())
+            .unwrap_or(0)
+            .max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "{level}: {}\n{gutter}--> {}:{line}:{col}\n{gutter} |\n{line} | {line_text}\n{gutter} | {}{}",
+            self.full_message(),
+            self.path.display(),
+            " ".repeat(col - 1),
+            "^".repeat(width),
+        )
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line_col() {
+            Some((line, col)) => {
+                write!(
+                    f,
+                    "{}:{line}:{col}: {}",
+                    self.path.display(),
+                    self.full_message()
+                )?;
+            }
+            None => write!(f, "{}: {}", self.path.display(), self.full_message())?,
+        }
+        for note in &self.notes {
+            write!(f, "\nnote: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// One part of the path to a key in a config.
+pub enum KeySegment {
+    /// A key in a table.
+    Key(String),
+    /// An index into an array.
+    Index(usize),
+}
+
+/// Converts the path to the value an error occurred at while deserializing
+/// into a key.
+pub fn path_to_key(path: &serde_path_to_error::Path) -> Vec<KeySegment> {
+    path.iter()
+        .filter_map(|segment| match segment {
+            serde_path_to_error::Segment::Map { key } => Some(KeySegment::Key(key.clone())),
+            serde_path_to_error::Segment::Seq { index } => Some(KeySegment::Index(*index)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Formats a path to a key the way it's shown in errors, like `pkg.formats.linux[0]`.
+pub fn format_key(key: &[KeySegment]) -> String {
+    let mut out = String::new();
+    for segment in key {
+        match segment {
+            KeySegment::Key(key) => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out += key;
+            }
+            KeySegment::Index(index) => out += &format!("[{}]", index),
+        }
+    }
+    out
+}
+
+/// Finds the span of the value at `key` in `source_text`. If `key` doesn't
+/// exist in `source_text`(for example because it was inherited), the span of
+/// the closest parent that does is used instead.
+pub fn find_span(source_text: &str, key: &[KeySegment]) -> Option<Range<usize>> {
+    let doc = toml_edit::ImDocument::parse(source_text).ok()?;
+    let mut node = Node::Item(doc.as_item());
+    let mut span = None;
+    for segment in key {
+        let Some(next) = node.child(segment) else {
+            break;
+        };
+        span = next.span().or(span);
+        node = next;
+    }
+    span
+}
+
+/// Anything in a [`toml_edit`] document that can have children.
+enum Node<'a> {
+    Item(&'a toml_edit::Item),
+    Value(&'a toml_edit::Value),
+    Table(&'a toml_edit::Table),
+}
+
+impl<'a> Node<'a> {
+    fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Node::Item(item) => item.span(),
+            Node::Value(value) => value.span(),
+            Node::Table(table) => table.span(),
+        }
+    }
+
+    fn child(&self, segment: &KeySegment) -> Option<Node<'a>> {
+        match (self, segment) {
+            (Node::Item(toml_edit::Item::Value(value)), _) => Node::Value(value).child(segment),
+            (Node::Item(toml_edit::Item::Table(table)), _) => Node::Table(table).child(segment),
+            (Node::Item(toml_edit::Item::ArrayOfTables(tables)), KeySegment::Index(index)) => {
+                tables.get(*index).map(Node::Table)
+            }
+            (Node::Table(table), KeySegment::Key(key)) => table.get(key).map(Node::Item),
+            (Node::Value(toml_edit::Value::InlineTable(table)), KeySegment::Key(key)) => {
+                table.get(key).map(Node::Value)
+            }
+            (Node::Value(toml_edit::Value::Array(array)), KeySegment::Index(index)) => {
+                array.get(*index).map(Node::Value)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Splitting a `rust-config.toml` across several files with `include`.
+//!
+//! ```toml
+//! include = ["crates/*.toml", "formats.toml"]
+//!
+//! [pkg]
+//! toolchains = [{ channel = "stable", crate-id = "web", ... }]
+//! ```
+//!
+//! Included files can only contain `crates`, `patches`, `formats`, and another
+//! `include`, and their paths(which can be globs) are relative to the file
+//! including them. A top-level `include`(or one in `[defaults]`) adds its
+//! crate, patch, and format lists to [`[defaults]`](crate::inherit), so every
+//! package gets them, while an `include` in a package only adds them to that
+//! package.
+//!
+//! A list can only be defined once, so if two files(or an included file and
+//! the including one) both define `crates.web`, that's an error pointing at
+//! both definitions. That includes a package defining a list that a top-level
+//! `include` added to `[defaults]`, which inheritance would otherwise merge
+//! into the package's own. Includes are resolved by [`resolve`], before
+//! [inheritance](crate::inherit::resolve).
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::{
+    error::{ConfigError, KeySegment},
+    inherit::DEFAULTS_KEY,
+    interpolate,
+    metadata::Metadata,
+    patch, LoadOptions,
+};
+
+/// The key used to include other files.
+pub const INCLUDE_KEY: &str = "include";
+/// The keys that can be set in an included file(other than [`INCLUDE_KEY`]).
+pub const INCLUDED_KEYS: [&str; 3] = ["crates", "patches", "formats"];
+
+#[derive(Debug, Clone)]
+/// A file read while resolving includes.
+pub struct SourceFile {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The contents of the file.
+    pub contents: String,
+}
+
+#[derive(Debug, Clone, Default)]
+/// The files read by [`resolve`], and which crate and format lists came from
+/// which file.
+pub struct Includes {
+    /// Every file that was read, starting with the including file.
+    pub files: Vec<SourceFile>,
+    /// Maps the package(`None` for `[defaults]`), the key(one of
+    /// [`INCLUDED_KEYS`]), and the ID of a list to the index of the file it was
+    /// defined in.
+    origins: HashMap<(Option<String>, String, String), usize>,
+}
+
+impl Includes {
+    /// Finds the included file a key in the resolved config was defined in,
+    /// along with the key in that file. Returns `None` if the key wasn't
+    /// defined in an included file.
+    pub fn locate(&self, key: &[KeySegment]) -> Option<(&SourceFile, Vec<KeySegment>)> {
+        let [KeySegment::Key(package), KeySegment::Key(section), KeySegment::Key(id), rest @ ..] =
+            key
+        else {
+            return None;
+        };
+        let file = self
+            .origins
+            .get(&(Some(package.clone()), section.clone(), id.clone()))
+            .or_else(|| self.origins.get(&(None, section.clone(), id.clone())))?;
+        let mut key = vec![
+            KeySegment::Key(section.clone()),
+            KeySegment::Key(id.clone()),
+        ];
+        key.extend(rest.iter().cloned());
+        Some((&self.files[*file], key))
+    }
+}
+
+/// Resolves every `include` in `config`, the parsed `contents` of `path`, in
+/// place. Variables in included files are [interpolated](interpolate) with
+/// `options`. If `path` is a `Cargo.toml`, `metadata` is where the config is
+/// in it.
+pub fn resolve(
+    config: &mut Table,
+    path: &Path,
+    contents: &str,
+    options: &LoadOptions,
+    metadata: Option<&Metadata>,
+) -> Result<Includes, ConfigError> {
+    let mut resolver = Resolver {
+        options,
+        metadata,
+        includes: Includes {
+            files: vec![SourceFile {
+                path: path.to_path_buf(),
+                contents: contents.to_string(),
+            }],
+            origins: HashMap::new(),
+        },
+        stack: vec![path.canonicalize().unwrap_or_else(|_| path.to_path_buf())],
+    };
+
+    if let Some(patterns) = config.remove(INCLUDE_KEY) {
+        let defaults = config
+            .entry(DEFAULTS_KEY)
+            .or_insert_with(|| Value::Table(Table::new()));
+        let Value::Table(defaults) = defaults else {
+            return Err(resolver.error(
+                0,
+                &[KeySegment::Key(DEFAULTS_KEY.to_string())],
+                "expected a table",
+            ));
+        };
+        let target_key = [KeySegment::Key(DEFAULTS_KEY.to_string())];
+        resolver.include(
+            0,
+            vec![KeySegment::Key(INCLUDE_KEY.to_string())],
+            patterns,
+            &mut Target {
+                table: defaults,
+                key: &target_key,
+                package: None,
+            },
+        )?;
+    }
+
+    for (name, package) in config.iter_mut() {
+        let Value::Table(package) = package else {
+            continue;
+        };
+        let Some(patterns) = package.remove(INCLUDE_KEY) else {
+            continue;
+        };
+        let target_key = [KeySegment::Key(name.clone())];
+        resolver.include(
+            0,
+            vec![
+                KeySegment::Key(name.clone()),
+                KeySegment::Key(INCLUDE_KEY.to_string()),
+            ],
+            patterns,
+            &mut Target {
+                table: package,
+                key: &target_key,
+                package: (name != DEFAULTS_KEY).then(|| name.clone()),
+            },
+        )?;
+    }
+
+    resolver.check_defaults(config)?;
+    Ok(resolver.includes)
+}
+
+/// The table included files are merged into.
+struct Target<'a> {
+    table: &'a mut Table,
+    /// The key of the table in the including file.
+    key: &'a [KeySegment],
+    /// The package the table is, or `None` for `[defaults]`.
+    package: Option<String>,
+}
+
+struct Resolver<'a> {
+    options: &'a LoadOptions,
+    metadata: Option<&'a Metadata>,
+    includes: Includes,
+    /// The canonical paths of the files currently being included, used to
+    /// detect cycles.
+    stack: Vec<PathBuf>,
+}
+
+impl Resolver<'_> {
+    /// Checks that no package defines a list that was included into
+    /// `[defaults]`.
+    fn check_defaults(&self, config: &Table) -> Result<(), ConfigError> {
+        for (name, package) in config {
+            let Value::Table(package) = package else {
+                continue;
+            };
+            if name == DEFAULTS_KEY {
+                continue;
+            }
+            for section in INCLUDED_KEYS {
+                let Some(Value::Table(lists)) = package.get(section) else {
+                    continue;
+                };
+                for id in lists.keys() {
+                    let Some(&included) =
+                        self.includes
+                            .origins
+                            .get(&(None, section.to_string(), id.clone()))
+                    else {
+                        continue;
+                    };
+                    let key = [
+                        KeySegment::Key(section.to_string()),
+                        KeySegment::Key(id.clone()),
+                    ];
+                    let message = format!("{} `{id}` is defined more than once", kind(section));
+                    let err = match self.includes.origins.get(&(
+                        Some(name.clone()),
+                        section.to_string(),
+                        id.clone(),
+                    )) {
+                        Some(&file) => self.error(file, &key, message),
+                        None => {
+                            let mut package_key = vec![KeySegment::Key(name.clone())];
+                            package_key.extend(key.iter().cloned());
+                            self.error(0, &package_key, message)
+                        }
+                    };
+                    return Err(err.with_note(self.error(included, &key, "first defined here")));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn error(&self, file: usize, key: &[KeySegment], message: impl Into<String>) -> ConfigError {
+        let key = match self.metadata {
+            Some(metadata) if file == 0 => metadata.file_key(key),
+            _ => key.to_vec(),
+        };
+        let file = &self.includes.files[file];
+        ConfigError::at(&file.path, &file.contents, &key, message)
+    }
+
+    /// Includes every file matched by `patterns`, found at `key` in `file`.
+    fn include(
+        &mut self,
+        file: usize,
+        key: Vec<KeySegment>,
+        patterns: Value,
+        target: &mut Target,
+    ) -> Result<(), ConfigError> {
+        let Value::Array(patterns) = patterns else {
+            return Err(self.error(file, &key, "expected an array of paths"));
+        };
+        let dir = self.includes.files[file]
+            .path
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        for (idx, pattern) in patterns.into_iter().enumerate() {
+            let mut pattern_key = key.clone();
+            pattern_key.push(KeySegment::Index(idx));
+            let Value::String(pattern) = pattern else {
+                return Err(self.error(file, &pattern_key, "expected a path"));
+            };
+            let paths =
+                expand(&dir, &pattern).map_err(|msg| self.error(file, &pattern_key, msg))?;
+            for path in paths {
+                self.include_file(path, file, &pattern_key, target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Includes the file at `path`, which was matched by the pattern at `key` in `from`.
+    fn include_file(
+        &mut self,
+        path: PathBuf,
+        from: usize,
+        key: &[KeySegment],
+        target: &mut Target,
+    ) -> Result<(), ConfigError> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.stack.contains(&canonical) {
+            return Err(self.error(from, key, format!("`{}` includes itself", path.display())));
+        }
+        let contents = fs::read_to_string(&path).map_err(|err| {
+            self.error(
+                from,
+                key,
+                format!("failed to read `{}`: {}", path.display(), err),
+            )
+        })?;
+        let mut table: Table = toml::from_str(&contents)
+            .map_err(|err| ConfigError::from_toml(&path, &contents, err))?;
+        interpolate::interpolate(&mut table, self.options)
+            .map_err(|err| ConfigError::at(&path, &contents, &err.key, err.message))?;
+        if let Some(patches) = table.get_mut("patches") {
+            patch::absolute_paths(patches, path.parent().unwrap_or(Path::new("")));
+        }
+        self.includes.files.push(SourceFile { path, contents });
+        let file = self.includes.files.len() - 1;
+
+        self.stack.push(canonical);
+        let mut nested = None;
+        for (key, value) in table {
+            match key.as_str() {
+                INCLUDE_KEY => nested = Some(value),
+                key if INCLUDED_KEYS.contains(&key) => self.merge(file, key, value, target)?,
+                _ => {
+                    return Err(self.error(
+                        file,
+                        &[KeySegment::Key(key)],
+                        "only `crates`, `patches`, `formats`, and `include` can be set in an included file",
+                    ))
+                }
+            }
+        }
+        if let Some(nested) = nested {
+            self.include(
+                file,
+                vec![KeySegment::Key(INCLUDE_KEY.to_string())],
+                nested,
+                target,
+            )?;
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Merges the lists in `value`, the `section` key of `file`, into `target`.
+    fn merge(
+        &mut self,
+        file: usize,
+        section: &str,
+        value: Value,
+        target: &mut Target,
+    ) -> Result<(), ConfigError> {
+        let Value::Table(lists) = value else {
+            return Err(self.error(
+                file,
+                &[KeySegment::Key(section.to_string())],
+                "expected a table",
+            ));
+        };
+        let existing = target
+            .table
+            .entry(section)
+            .or_insert_with(|| Value::Table(Table::new()));
+        let Value::Table(existing) = existing else {
+            let mut key = target.key.to_vec();
+            key.push(KeySegment::Key(section.to_string()));
+            return Err(self.error(0, &key, "expected a table"));
+        };
+        for (id, list) in lists {
+            let origin = (target.package.clone(), section.to_string(), id.clone());
+            let key = [
+                KeySegment::Key(section.to_string()),
+                KeySegment::Key(id.clone()),
+            ];
+            if existing.contains_key(&id) {
+                let first = match self.includes.origins.get(&origin) {
+                    Some(&first) => self.error(first, &key, "first defined here"),
+                    None => {
+                        let mut first_key = target.key.to_vec();
+                        first_key.extend(key.iter().cloned());
+                        self.error(0, &first_key, "first defined here")
+                    }
+                };
+                return Err(self
+                    .error(
+                        file,
+                        &key,
+                        format!("{} `{id}` is defined more than once", kind(section)),
+                    )
+                    .with_note(first));
+            }
+            existing.insert(id, list);
+            self.includes.origins.insert(origin, file);
+        }
+        Ok(())
+    }
+}
+
+/// What the lists in `section`(one of [`INCLUDED_KEYS`]) are called in errors.
+fn kind(section: &str) -> &'static str {
+    match section {
+        "crates" => "crate list",
+        "patches" => "patch list",
+        _ => "format list",
+    }
+}
+
+/// Expands `pattern`, relative to `dir`. A pattern that isn't a glob has to
+/// match an existing file.
+fn expand(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let full = if Path::new(pattern).is_absolute() || dir.as_os_str().is_empty() {
+        pattern.to_string()
+    } else {
+        format!(
+            "{}/{}",
+            glob::Pattern::escape(&dir.to_string_lossy()),
+            pattern
+        )
+    };
+    let paths = glob::glob(&full)
+        .map_err(|err| format!("invalid pattern `{}`: {}", pattern, err))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())?;
+    if paths.is_empty() && !pattern.contains(['*', '?', '[']) {
+        return Err(format!("`{}` doesn't exist", dir.join(pattern).display()));
+    }
+    Ok(paths)
+}
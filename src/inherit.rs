@@ -0,0 +1,270 @@
+//! Config inheritance, through the top-level `[defaults]` table and `extends` keys.
+//!
+//! ```toml
+//! [defaults]
+//! formats = { linux = ["gz-only"] }
+//!
+//! [defaults.toolchain]
+//! components = ["rustc", "cargo", "rust-std"]
+//! platforms = ["x86_64-unknown-linux-gnu"]
+//! format-map = { "x86_64-unknown-linux-gnu" = "linux" }
+//!
+//! [base]
+//! toolchains = [{ channel = "stable", crate-id = "base" }]
+//! crates = { base = { serde = "1" } }
+//!
+//! [web]
+//! extends = "base"
+//! crates = { base = { axum = "0.7" } }
+//!
+//! [nightly]
+//! toolchains = [{ extends = "base", channel = "nightly", crate-id = "base" }]
+//! crates = { base = {} }
+//! ```
+//!
+//! Every key in `[defaults]` other than `toolchain` is merged into every
+//! package, and `[defaults.toolchain]` is merged into every toolchain. A
+//! package with `extends = "other"` is merged on top of `other`, and a
+//! toolchain with `extends = "other"`(or `"other[1]"` if `other` has more than
+//! one toolchain) is merged on top of that toolchain.
+//!
+//! Merging is deep: tables are merged key by key, while anything else(including
+//! arrays) set in the inheriting table replaces the inherited value. Everything
+//! is resolved by [`resolve`] before the config is deserialized or validated.
+
+use std::collections::HashMap;
+
+use toml::{Table, Value};
+
+use crate::error::KeySegment;
+
+/// The name of the top-level table containing the defaults for every package.
+pub const DEFAULTS_KEY: &str = "defaults";
+/// The key in [`DEFAULTS_KEY`] containing the defaults for every toolchain.
+pub const TOOLCHAIN_DEFAULTS_KEY: &str = "toolchain";
+/// The key used to inherit from another package or toolchain.
+pub const EXTENDS_KEY: &str = "extends";
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error produced while resolving inheritance.
+pub struct InheritError {
+    /// The key the error occurred at.
+    pub key: Vec<KeySegment>,
+    /// A description of the error.
+    pub message: String,
+}
+
+impl InheritError {
+    fn new(key: Vec<KeySegment>, message: String) -> InheritError {
+        InheritError { key, message }
+    }
+}
+
+/// Merges `over` into `base`. Tables are merged recursively; anything else in
+/// `over` replaces the value in `base`.
+pub fn deep_merge(base: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(over)) => deep_merge(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolves the `[defaults]` table and every `extends` key in a whole
+/// `rust-config.toml`, in place. Afterwards, `config` only contains packages.
+pub fn resolve(config: &mut Table) -> Result<(), InheritError> {
+    let mut defaults = match config.remove(DEFAULTS_KEY) {
+        Some(Value::Table(defaults)) => defaults,
+        Some(_) => {
+            return Err(InheritError::new(
+                vec![KeySegment::Key(DEFAULTS_KEY.to_string())],
+                "expected a table".to_string(),
+            ))
+        }
+        None => Table::new(),
+    };
+    let toolchain_defaults = match defaults.remove(TOOLCHAIN_DEFAULTS_KEY) {
+        Some(Value::Table(defaults)) => defaults,
+        Some(_) => {
+            return Err(InheritError::new(
+                vec![
+                    KeySegment::Key(DEFAULTS_KEY.to_string()),
+                    KeySegment::Key(TOOLCHAIN_DEFAULTS_KEY.to_string()),
+                ],
+                "expected a table".to_string(),
+            ))
+        }
+        None => Table::new(),
+    };
+
+    let mut resolver = Resolver {
+        raw: config,
+        defaults,
+        toolchain_defaults,
+        resolved: HashMap::new(),
+        stack: Vec::new(),
+    };
+    let names: Vec<String> = resolver.raw.keys().cloned().collect();
+    for name in &names {
+        resolver.package(name, Vec::new())?;
+    }
+    let mut resolved = resolver.resolved;
+    for name in names {
+        let package = resolved.remove(&name).unwrap();
+        config.insert(name, Value::Table(package));
+    }
+    Ok(())
+}
+
+struct Resolver<'a> {
+    raw: &'a Table,
+    defaults: Table,
+    toolchain_defaults: Table,
+    resolved: HashMap<String, Table>,
+    /// The packages currently being resolved, used to detect cycles.
+    stack: Vec<String>,
+}
+
+impl Resolver<'_> {
+    /// Resolves the package `name`. `key` is where it was referenced from, for errors.
+    fn package(&mut self, name: &str, key: Vec<KeySegment>) -> Result<&Table, InheritError> {
+        if self.resolved.contains_key(name) {
+            return Ok(&self.resolved[name]);
+        }
+        let package_key = vec![KeySegment::Key(name.to_string())];
+        if self.stack.iter().any(|n| n == name) {
+            return Err(InheritError::new(
+                key,
+                format!(
+                    "`{}` inherits from itself (through {})",
+                    name,
+                    self.stack
+                        .iter()
+                        .map(|n| format!("`{}`", n))
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                ),
+            ));
+        }
+        let Some(raw) = self.raw.get(name) else {
+            return Err(InheritError::new(
+                key,
+                format!("package `{}` doesn't exist", name),
+            ));
+        };
+        let Value::Table(raw) = raw else {
+            return Err(InheritError::new(
+                package_key,
+                "expected a table".to_string(),
+            ));
+        };
+        let mut raw = raw.clone();
+
+        self.stack.push(name.to_string());
+        let mut package = match raw.remove(EXTENDS_KEY) {
+            Some(Value::String(base)) => {
+                let mut extends_key = package_key.clone();
+                extends_key.push(KeySegment::Key(EXTENDS_KEY.to_string()));
+                self.package(&base, extends_key)?.clone()
+            }
+            Some(_) => {
+                let mut extends_key = package_key.clone();
+                extends_key.push(KeySegment::Key(EXTENDS_KEY.to_string()));
+                return Err(InheritError::new(
+                    extends_key,
+                    "expected the name of a package".to_string(),
+                ));
+            }
+            None => self.defaults.clone(),
+        };
+        deep_merge(&mut package, raw);
+
+        if let Some(Value::Array(toolchains)) = package.get("toolchains") {
+            let mut out = Vec::with_capacity(toolchains.len());
+            for (idx, toolchain) in toolchains.clone().into_iter().enumerate() {
+                let mut toolchain_key = package_key.clone();
+                toolchain_key.push(KeySegment::Key("toolchains".to_string()));
+                toolchain_key.push(KeySegment::Index(idx));
+                out.push(self.toolchain(toolchain, toolchain_key)?);
+            }
+            package.insert("toolchains".to_string(), Value::Array(out));
+        }
+        self.stack.pop();
+
+        self.resolved.insert(name.to_string(), package);
+        Ok(&self.resolved[name])
+    }
+
+    /// Resolves a single toolchain, at `key`.
+    fn toolchain(&mut self, toolchain: Value, key: Vec<KeySegment>) -> Result<Value, InheritError> {
+        let Value::Table(mut raw) = toolchain else {
+            // left for deserialization to report
+            return Ok(toolchain);
+        };
+        let mut toolchain = self.toolchain_defaults.clone();
+        match raw.remove(EXTENDS_KEY) {
+            Some(Value::String(base)) => {
+                let mut extends_key = key.clone();
+                extends_key.push(KeySegment::Key(EXTENDS_KEY.to_string()));
+                let (name, index) = parse_toolchain_ref(&base).ok_or_else(|| {
+                    InheritError::new(
+                        extends_key.clone(),
+                        format!("invalid toolchain reference `{}`", base),
+                    )
+                })?;
+                let package = self.package(name, extends_key.clone())?;
+                let toolchains = match package.get("toolchains") {
+                    Some(Value::Array(toolchains)) => toolchains.as_slice(),
+                    _ => &[],
+                };
+                let base_toolchain = match index {
+                    Some(index) => toolchains.get(index),
+                    None if toolchains.len() == 1 => toolchains.first(),
+                    None => {
+                        return Err(InheritError::new(
+                            extends_key,
+                            format!(
+                                "package `{}` has {} toolchains, use `{}[<index>]` to pick one",
+                                name,
+                                toolchains.len(),
+                                name
+                            ),
+                        ))
+                    }
+                };
+                let Some(Value::Table(base_toolchain)) = base_toolchain else {
+                    return Err(InheritError::new(
+                        extends_key,
+                        format!("toolchain `{}` doesn't exist", base),
+                    ));
+                };
+                deep_merge(&mut toolchain, base_toolchain.clone());
+            }
+            Some(_) => {
+                let mut extends_key = key;
+                extends_key.push(KeySegment::Key(EXTENDS_KEY.to_string()));
+                return Err(InheritError::new(
+                    extends_key,
+                    "expected the name of a package".to_string(),
+                ));
+            }
+            None => {}
+        }
+        deep_merge(&mut toolchain, raw);
+        Ok(Value::Table(toolchain))
+    }
+}
+
+/// Parses `package` or `package[index]`.
+fn parse_toolchain_ref(reference: &str) -> Option<(&str, Option<usize>)> {
+    match reference.strip_suffix(']') {
+        Some(rest) => {
+            let (name, index) = rest.rsplit_once('[')?;
+            Some((name, Some(index.parse().ok()?)))
+        }
+        None => Some((reference, None)),
+    }
+}
@@ -0,0 +1,213 @@
+//! Bootstrapping a package from an existing project, which is what
+//! `rust-pkg-gen init --from` does.
+//!
+//! The toolchain comes from the project's `rust-toolchain.toml`(or legacy
+//! `rust-toolchain`) file, and the crates from the registry packages in its
+//! `Cargo.lock`, each pinned to the exact version that's locked(or to every
+//! version, if several are locked). The only
+//! platform is the host, with its installer(if it has one) and gzip tarballs
+//! as its formats.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use serde::Deserialize;
+
+use crate::{Crate, Format, FormatKind, RustConfigInner, Suffix, Toolchain};
+
+/// The components every package gets, in addition to the ones in the
+/// toolchain file, unless it has a `profile`.
+pub const DEFAULT_COMPONENTS: [&str; 3] = ["rustc", "cargo", "rust-std"];
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+/// The `[toolchain]` table of a `rust-toolchain.toml` file.
+pub struct ToolchainFile {
+    /// The channel, like `stable` or `1.83.0`.
+    pub channel: Option<String>,
+    /// The component profile, like `minimal` or `default`.
+    pub profile: Option<String>,
+    /// Extra components to install.
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Extra targets to install.
+    #[serde(default)]
+    pub targets: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ToolchainFileOuter {
+    toolchain: ToolchainFile,
+}
+
+/// Reads the toolchain file in `dir`, if there is one.
+pub fn read_toolchain_file(dir: &Path) -> Result<Option<ToolchainFile>, Error> {
+    for name in ["rust-toolchain.toml", "rust-toolchain"] {
+        let path = dir.join(name);
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        // the legacy format is just the name of the channel
+        if name == "rust-toolchain" && !contents.contains('[') {
+            return Ok(Some(ToolchainFile {
+                channel: Some(contents.trim().to_string()),
+                ..ToolchainFile::default()
+            }));
+        }
+        let file: ToolchainFileOuter = toml::from_str(&contents)
+            .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))?;
+        return Ok(Some(file.toolchain));
+    }
+    Ok(None)
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// A package in a `Cargo.lock` file.
+pub struct LockedPackage {
+    /// The name of the crate.
+    pub name: String,
+    /// The exact version that's locked.
+    pub version: String,
+    /// Where the crate comes from, like `registry+https://github.com/rust-lang/crates.io-index`.
+    /// `None` for path dependencies and workspace members.
+    pub source: Option<String>,
+}
+
+impl LockedPackage {
+    /// Whether the package comes from a registry, rather than a path or git.
+    pub fn is_registry(&self) -> bool {
+        self.source
+            .as_ref()
+            .is_some_and(|source| source.starts_with("registry+") || source.starts_with("sparse+"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+/// Reads the registry packages out of the `Cargo.lock` file of the project in
+/// `dir`, looking in its parents too in case it's in a workspace.
+pub fn read_lockfile(dir: &Path) -> Result<Vec<LockedPackage>, Error> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let path = dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            anyhow!(
+                "no Cargo.lock found for {}(run `cargo generate-lockfile` first)",
+                dir.display()
+            )
+        })?;
+    let contents = fs::read_to_string(&path)
+        .map_err(|err| anyhow!("failed to read {}: {}", path.display(), err))?;
+    let lockfile: Lockfile = toml::from_str(&contents)
+        .map_err(|err| anyhow!("failed to parse {}: {}", path.display(), err))?;
+    Ok(lockfile
+        .package
+        .into_iter()
+        .filter(LockedPackage::is_registry)
+        .collect())
+}
+
+/// The name of the project in `dir`: its package name if it has one, or the
+/// name of the directory otherwise.
+pub fn project_name(dir: &Path) -> String {
+    let name = fs::read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|contents| contents.parse::<toml::Table>().ok())
+        .and_then(|manifest| Some(manifest.get("package")?.get("name")?.as_str()?.to_string()));
+    name.unwrap_or_else(|| {
+        dir.canonicalize()
+            .unwrap_or_else(|_| PathBuf::from(dir))
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "project".to_string())
+    })
+}
+
+/// Creates a package for the project in `dir`, for the platform `host`.
+/// Returns the name of the package along with it.
+pub fn package_from_project(dir: &Path, host: &str) -> Result<(String, RustConfigInner), Error> {
+    let name = project_name(dir);
+    let toolchain_file = read_toolchain_file(dir)?.unwrap_or_default();
+
+    // a profile already has the default components
+    let mut components: Vec<String> = match toolchain_file.profile {
+        Some(_) => Vec::new(),
+        None => DEFAULT_COMPONENTS.map(String::from).to_vec(),
+    };
+    for component in toolchain_file.components {
+        if !components.contains(&component) {
+            components.push(component);
+        }
+    }
+
+    // the lockfile is sorted by name and then version, so putting each version
+    // first makes the newest one keep the crate's name when it's lowered
+    let mut locked: HashMap<String, Vec<Crate>> = HashMap::new();
+    for package in read_lockfile(dir)? {
+        locked
+            .entry(package.name)
+            .or_default()
+            .insert(0, Crate::Version(format!("={}", package.version)));
+    }
+    let crates = locked
+        .into_iter()
+        .map(|(name, mut versions)| {
+            let krate = match versions.len() {
+                1 => versions.remove(0),
+                _ => Crate::Versions(versions),
+            };
+            (name, krate)
+        })
+        .collect();
+
+    let mut formats = Vec::new();
+    if host.contains("windows") {
+        formats.push(Format {
+            format: FormatKind::Msi,
+            suffix: Suffix::IfAvailable,
+        });
+    } else if host.contains("apple") {
+        formats.push(Format {
+            format: FormatKind::Pkg,
+            suffix: Suffix::IfAvailable,
+        });
+    }
+    formats.push(Format {
+        format: FormatKind::Gz,
+        suffix: Suffix::IfAvailable,
+    });
+
+    #[allow(deprecated)]
+    let toolchain = Toolchain {
+        edition: None,
+        channel: toolchain_file
+            .channel
+            .unwrap_or_else(|| "stable".to_string()),
+        profile: toolchain_file.profile,
+        components,
+        crate_id: name.clone(),
+        crate_ids: Vec::new(),
+        exclude_crates: Vec::new(),
+        platforms: vec![host.to_string()],
+        targets: toolchain_file.targets,
+        format_map: HashMap::from([(host.to_string(), host.to_string())]),
+    };
+    Ok((
+        name.clone(),
+        RustConfigInner {
+            toolchains: vec![toolchain],
+            crates: HashMap::from([(name, crates)]),
+            formats: HashMap::from([(host.to_string(), formats)]),
+            patches: HashMap::new(),
+        },
+    ))
+}
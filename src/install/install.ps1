@@ -77,7 +77,6 @@ Push-Location .
 $USING_MSI = $env:USING_MSI ?? &?TOOLCHAIN.MSI
 $VERSION = $PSVersionTable.PSVersion.Major ?? 1
 
-$COMPONENTS = $env:COMPONENTS ?? "&?TOOLCHAIN.COMPONENTS"
 $CHANNEL = $env:CHANNEL ?? "&?TOOLCHAIN.CHANNEL"
 
 $TAR_FLAGS = $env:TAR_FLAGS ?? "-xf"
@@ -96,6 +95,11 @@ if ([System.Environment]::Is64BitProcess) {
 
 $target_triple = "$arch-pc-windows-msvc"
 
+$PLATFORM_COMPONENTS = @{
+&?TOOLCHAIN.COMPONENTS_TABLE
+}
+$COMPONENTS = $env:COMPONENTS ?? $PLATFORM_COMPONENTS[$target_triple]
+
 $COMPONENTS.Split(" ") | ForEach-Object {
     $comp = $_
     if (! $(Test-Path "$PARENT_DIR\toolchain\$comp-$CHANNEL-$target_triple.$FORMAT" -PathType Leaf)) {
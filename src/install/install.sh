@@ -355,7 +355,6 @@ PARENT_DIR=$(
     pwd
 )
 
-COMPONENTS="${COMPONENTS:-&?TOOLCHAIN.COMPONENTS}"
 CHANNEL="${CHANNEL:-&?TOOLCHAIN.CHANNEL}"
 FORMAT="${FORMAT:-.tar.gz}"
 TAR_FLAGS="${TAR_FLAGS:-"-xf"}"
@@ -403,6 +402,17 @@ fi
 
 target_triple=$(get_architecture)
 
+if [ -z "$COMPONENTS" ]; then
+    case "$target_triple" in
+&?TOOLCHAIN.COMPONENTS_CASES
+    *)
+        echo "This package doesn't have a toolchain for $target_triple!"
+        cleanup
+        exit 1
+        ;;
+    esac
+fi
+
 if [ "$_cputype" = "apple-darwin" ] && [ -n "$USING_PKG" ]; then
     installer -pkg "$PARENT_DIR/toolchain/rust-$CHANNEL-$target_triple.pkg" -target CurrentUserHomeDirectory
 fi
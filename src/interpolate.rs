@@ -0,0 +1,218 @@
+//! `${VAR}` interpolation and `--set` overrides, so that one config can be
+//! used for several channels or mirrors without templating it first.
+//!
+//! Every string value in a config(and in every [included](crate::include)
+//! file) can contain `${VAR}`, which is replaced with the environment variable
+//! `VAR`, or `${VAR:-default}`, which falls back to `default` if `VAR` isn't
+//! set or is empty. `$${` is a literal `${`. Variables are looked up with
+//! [`LoadOptions::var`].
+//!
+//! [`Override`]s set a single key, like `pkg.toolchains[0].channel=beta`, after
+//! includes are resolved but before [inheritance](crate::inherit) is, so they
+//! behave the same as editing the file would.
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Error};
+use toml::{Table, Value};
+
+use crate::{
+    error::{format_key, KeySegment},
+    LoadOptions,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// An error produced while interpolating variables or applying overrides.
+pub struct InterpolateError {
+    /// The key the error occurred at.
+    pub key: Vec<KeySegment>,
+    /// A description of the error.
+    pub message: String,
+}
+
+/// Interpolates every string value in `table`, in place.
+pub fn interpolate(table: &mut Table, options: &LoadOptions) -> Result<(), InterpolateError> {
+    let mut key = Vec::new();
+    for (name, value) in table.iter_mut() {
+        key.push(KeySegment::Key(name.clone()));
+        interpolate_value(value, &mut key, options)?;
+        key.pop();
+    }
+    Ok(())
+}
+
+fn interpolate_value(
+    value: &mut Value,
+    key: &mut Vec<KeySegment>,
+    options: &LoadOptions,
+) -> Result<(), InterpolateError> {
+    match value {
+        Value::String(s) => {
+            *s = interpolate_str(s, options).map_err(|message| InterpolateError {
+                key: key.clone(),
+                message,
+            })?;
+        }
+        Value::Array(array) => {
+            for (idx, value) in array.iter_mut().enumerate() {
+                key.push(KeySegment::Index(idx));
+                interpolate_value(value, key, options)?;
+                key.pop();
+            }
+        }
+        Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                key.push(KeySegment::Key(name.clone()));
+                interpolate_value(value, key, options)?;
+                key.pop();
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Interpolates a single string. See the [module documentation](self).
+pub fn interpolate_str(s: &str, options: &LoadOptions) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(idx) = rest.find('$') {
+        out += &rest[..idx];
+        rest = &rest[idx..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            out += "${";
+            rest = after;
+            continue;
+        }
+        let Some(after) = rest.strip_prefix("${") else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(end) = after.find('}') else {
+            return Err(format!("unterminated `${{` in `{}`", s));
+        };
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid variable name `{}`", name));
+        }
+        out += &match (options.var(name), default) {
+            (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+            (Some(value), _) => value,
+            (None, Some(default)) => default.to_string(),
+            (None, None) if options.allow_undefined => String::new(),
+            (None, None) => return Err(format!("environment variable `{}` isn't set", name)),
+        };
+        rest = &after[end + 1..];
+    }
+    out += rest;
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Sets the value at a key, like `--set pkg.toolchains[0].channel=beta` does.
+///
+/// Parsed from `key=value`. The key is dotted, with `[index]` for array
+/// elements and `"quotes"` around parts containing dots. The value is parsed as
+/// TOML if it starts with `[`, `{`, or `"`, and is a string otherwise.
+pub struct Override {
+    /// The key to set.
+    pub key: Vec<KeySegment>,
+    /// The value to set it to.
+    pub value: Value,
+}
+
+impl Override {
+    /// Applies the override to `table`. Missing tables are created, but arrays
+    /// have to already have the element being set.
+    pub fn apply(&self, table: &mut Table) -> Result<(), InterpolateError> {
+        let error = |len: usize, message: &str| InterpolateError {
+            key: self.key[..len].to_vec(),
+            message: message.to_string(),
+        };
+        let Some((KeySegment::Key(first), _)) = self.key.split_first() else {
+            return Err(error(0, "the key has to start with a name"));
+        };
+        let mut current = table
+            .entry(first.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        for (idx, segment) in self.key.iter().enumerate().skip(1) {
+            current = match (current, segment) {
+                (Value::Table(table), KeySegment::Key(key)) => table
+                    .entry(key.clone())
+                    .or_insert_with(|| Value::Table(Table::new())),
+                (Value::Array(array), KeySegment::Index(index)) => array
+                    .get_mut(*index)
+                    .ok_or_else(|| error(idx + 1, "doesn't exist"))?,
+                (Value::Table(_), KeySegment::Index(_)) => {
+                    return Err(error(idx, "is a table, not an array"))
+                }
+                (_, KeySegment::Key(_)) => return Err(error(idx, "isn't a table")),
+                (_, KeySegment::Index(_)) => return Err(error(idx, "isn't an array")),
+            };
+        }
+        *current = self.value.clone();
+        Ok(())
+    }
+}
+
+impl FromStr for Override {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Override, Error> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `key=value`, found `{}`", s))?;
+        let key = parse_key(key.trim())?;
+        let value = if value.starts_with(['[', '{', '"']) {
+            toml::from_str::<Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .ok_or_else(|| anyhow!("invalid TOML value `{}`", value))?
+        } else {
+            Value::String(value.to_string())
+        };
+        Ok(Override { key, value })
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", format_key(&self.key), self.value)
+    }
+}
+
+/// Parses a key like `pkg.toolchains[0]."format-map"`.
+fn parse_key(s: &str) -> Result<Vec<KeySegment>, Error> {
+    let invalid = || anyhow!("invalid key `{}`", s);
+    let mut key = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let (index, after) = after.split_once(']').ok_or_else(invalid)?;
+            key.push(KeySegment::Index(index.parse().map_err(|_| invalid())?));
+            rest = after;
+        } else {
+            if !key.is_empty() {
+                rest = rest.strip_prefix('.').ok_or_else(invalid)?;
+            }
+            let name;
+            if let Some(after) = rest.strip_prefix('"') {
+                (name, rest) = after.split_once('"').ok_or_else(invalid)?;
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                (name, rest) = rest.split_at(end);
+            }
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            key.push(KeySegment::Key(name.to_string()));
+        }
+    }
+    if key.is_empty() {
+        return Err(invalid());
+    }
+    Ok(key)
+}
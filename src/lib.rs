@@ -1,20 +1,44 @@
 #![warn(missing_docs)]
+// `ConfigError` is big, but it's only ever returned once, right before exiting.
+#![allow(clippy::result_large_err)]
 
 //! Main file for the crate. Contains most of the public API,
 //! outside of mainly the code copied from [rustup-mirror](https://github.com/jiegec/rustup-mirror)
 //! and the resources included in the output.
 
 use anyhow::{anyhow, Error};
-use serde::Deserialize;
-use std::{collections::HashMap, fs, path::Path};
+use cfg::{CfgExpr, TargetCfg};
+use error::{format_key, ConfigError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::Path,
+    str::FromStr,
+};
 
+pub mod cfg;
+pub mod channel;
 pub mod copied;
+pub mod error;
+pub mod include;
+pub mod inherit;
+pub mod init;
+pub mod interpolate;
+pub mod metadata;
+pub mod normalize;
+pub mod patch;
 pub mod resources;
+pub mod schema;
+pub mod select;
 pub mod targets;
 #[cfg(test)]
 mod tests;
+pub mod validate;
+pub mod versions;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 #[serde(rename_all = "kebab-case")]
 /// Contains all relevant information for a toolchain
 ///
@@ -26,23 +50,103 @@ pub struct Toolchain {
     ///
     /// Deprecated as it's pretty much unnecessary
     #[deprecated(since = "1.2.0")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub edition: Option<String>,
-    /// The channel of rust to use. Should be in [`targets::RELEASE_CHANNELS`].
+    /// The channel of rust to use. Can be anything in [`targets::RELEASE_CHANNELS`],
+    /// a version(`1.83.0` or `1.82`), or a dated channel(`nightly-2024-11-30`).
+    /// See [`channel::ToolchainSpec`].
+    #[schemars(schema_with = "schema::channel")]
     pub channel: String,
-    /// The components of rust to install.
+    /// The [component profile](channel::PROFILES) to install, like rustup's
+    /// `--profile`. Resolved against the channel manifest when it's
+    /// downloaded; see [`channel::resolve_components`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(schema_with = "schema::profile")]
+    pub profile: Option<String>,
+    /// The components of rust to install, on top of the ones in the
+    /// [`profile`](Toolchain::profile).
+    #[serde(default)]
     pub components: Vec<String>,
     /// The ID used to index into the [`Crates`] instance associated with the
     /// rust config(technically [`RustConfigInner`], but whatever).
+    ///
+    /// Can be left empty if [`crate_ids`](Toolchain::crate_ids) is set.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub crate_id: String,
-    /// The list of targets to provide the rust components for.
+    /// More crate IDs, whose crate lists are combined with the one for
+    /// [`crate_id`](Toolchain::crate_id). See [`RustConfigInner::crates_for`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub crate_ids: Vec<String>,
+    /// Crates to leave out of the combined crate list.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_crates: Vec<String>,
+    /// The list of targets to provide the rust components for. Can contain
+    /// [`host`, aliases, and globs](targets::is_pattern), which are
+    /// [expanded](Toolchain::expanded) when the package is built. Defaults to
+    /// just [`host`](targets::HOST).
+    #[serde(default = "default_platforms")]
+    #[schemars(schema_with = "schema::targets")]
     pub platforms: Vec<String>,
-    /// The list of targets to allow the [`platforms`](Toolchain::platforms) to build for.
+    /// The list of targets to allow the [`platforms`](Toolchain::platforms) to
+    /// build for. Can contain `host`, aliases, and globs, like `platforms`.
+    #[schemars(schema_with = "schema::targets")]
     pub targets: Vec<String>,
     /// A map of [`platforms`](Toolchain::platforms) to format IDs. Format IDs are used to
     /// index into the [rust config's format list](RustConfigInner::formats).
+    /// Keys can be `host`, aliases, and globs too; see [`Toolchain::format_for`].
+    #[serde(serialize_with = "serialize_sorted")]
     pub format_map: HashMap<String, String>,
 }
 
+/// The default [`Toolchain::platforms`]: just the [host](targets::HOST).
+fn default_platforms() -> Vec<String> {
+    vec![targets::HOST.to_string()]
+}
+
+impl Toolchain {
+    /// Every crate ID the toolchain uses: [`crate_id`](Toolchain::crate_id)(if
+    /// it's set), followed by [`crate_ids`](Toolchain::crate_ids).
+    pub fn all_crate_ids(&self) -> Vec<&str> {
+        let mut ids = Vec::new();
+        if !self.crate_id.is_empty() {
+            ids.push(self.crate_id.as_str());
+        }
+        for id in &self.crate_ids {
+            if !ids.contains(&id.as_str()) {
+                ids.push(id);
+            }
+        }
+        ids
+    }
+
+    /// The toolchain with the [host, aliases, and globs](targets::is_pattern) in its
+    /// [`platforms`](Toolchain::platforms) and [`targets`](Toolchain::targets)
+    /// [expanded](targets::expand) against `available`.
+    pub fn expanded(&self, available: &[impl AsRef<str>]) -> Result<Toolchain, String> {
+        let mut toolchain = self.clone();
+        toolchain.platforms = targets::expand(&self.platforms, available)?;
+        toolchain.targets = targets::expand(&self.targets, available)?;
+        Ok(toolchain)
+    }
+
+    /// The format ID for `platform` in the [`format_map`](Toolchain::format_map):
+    /// its own entry, or else the longest(so most specific) entry that's a
+    /// [pattern](targets::is_pattern) matching it.
+    pub fn format_for(&self, platform: &str) -> Option<&String> {
+        self.format_map.get(platform).or_else(|| {
+            self.format_map
+                .iter()
+                .filter(|(pattern, _)| {
+                    targets::is_pattern(pattern) && targets::matches(pattern, platform)
+                })
+                // ties are broken by name so the result doesn't depend on the
+                // order of the map
+                .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+                .map(|(_, id)| id)
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 /// The suffix used for [Formats](Format).
 pub enum Suffix {
@@ -53,6 +157,107 @@ pub enum Suffix {
     Only,
 }
 
+impl Suffix {
+    /// The string used for this suffix in a config, excluding the leading `-`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Suffix::IfAvailable => "if-available",
+            Suffix::Only => "only",
+        }
+    }
+}
+
+impl FromStr for Suffix {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Suffix, Error> {
+        match s {
+            "only" => Ok(Suffix::Only),
+            "if-available" => Ok(Suffix::IfAvailable),
+            _ => Err(anyhow!("invalid suffix {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Suffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Suffix {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The kind of package produced for a platform. Used in a [`Format`].
+pub enum FormatKind {
+    /// A windows installer. Only available on windows platforms, and
+    /// only installs rustc, cargo, rustdoc, and rust-std.
+    Msi,
+    /// A macOS installer. Only available on apple platforms, and
+    /// only installs rustc, cargo, rustdoc, and rust-std.
+    Pkg,
+    /// The gzip-compressed tarballs of each component.
+    Gz,
+    /// The xz-compressed tarballs of each component.
+    Xz,
+}
+
+impl FormatKind {
+    /// Every known format kind.
+    pub const ALL: [FormatKind; 4] = [
+        FormatKind::Msi,
+        FormatKind::Pkg,
+        FormatKind::Gz,
+        FormatKind::Xz,
+    ];
+
+    /// The string used for this format in a config.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FormatKind::Msi => "msi",
+            FormatKind::Pkg => "pkg",
+            FormatKind::Gz => "gz",
+            FormatKind::Xz => "xz",
+        }
+    }
+
+    /// Whether this is an installer format(msi or pkg) rather than a tarball.
+    pub fn is_installer(&self) -> bool {
+        matches!(self, FormatKind::Msi | FormatKind::Pkg)
+    }
+}
+
+impl FromStr for FormatKind {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<FormatKind, Error> {
+        FormatKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or_else(|| anyhow!("invalid format {}", s))
+    }
+}
+
+impl fmt::Display for FormatKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for FormatKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 struct StringVisitor;
 
 impl<'de> serde::de::Visitor<'de> for StringVisitor {
@@ -75,23 +280,46 @@ impl<'de> serde::de::Visitor<'de> for StringVisitor {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-/// A format that includes one of the allowed [formats](FORMATS)
-/// and a [`Suffix`].
+/// A format that includes a [`FormatKind`] and a [`Suffix`].
 pub struct Format {
-    /// The actual format. One of [`FORMATS`].
-    pub format: String,
+    /// The actual format.
+    pub format: FormatKind,
     /// The suffix. See [the type documentation](Suffix) for information about the valid values.
     pub suffix: Suffix,
 }
 
 /// Slice of the formats that can be used in a [`Format`].
+#[deprecated(since = "1.2.0", note = "use `FormatKind::ALL` instead")]
 pub const FORMATS: [&str; 4] = ["msi", "pkg", "gz", "xz"];
 
+/// A format as it was written, without checking that the kind is known. Only
+/// produced by the lenient [`Format::parse_lenient`]; configs always go
+/// through the strict [`Format::from_string`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFormat {
+    /// The format as written, which may not be one of [`FormatKind::ALL`].
+    pub format: String,
+    /// The suffix. Anything other than `only` becomes [`Suffix::IfAvailable`].
+    pub suffix: Suffix,
+}
+
+impl RawFormat {
+    /// Converts this into a [`Format`], failing if the kind isn't known.
+    pub fn to_format(&self) -> Result<Format, Error> {
+        Ok(Format {
+            format: self.format.parse()?,
+            suffix: self.suffix.clone(),
+        })
+    }
+}
+
 impl Format {
     /// this function is a basic wrapper around and thus
     /// has the same semantic meaning as [`Format::from_string`]
+    #[deprecated(since = "1.2.0", note = "use `str::parse` instead")]
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(base_string: &str) -> Result<Format, Error> {
-        Format::from_string(base_string.to_string())
+        base_string.parse()
     }
     /// this function is a basic wrapper around and thus
     /// has the same semantic meaning as [`Format::from_string_no_err`]
@@ -109,9 +337,9 @@ impl Format {
     ///
     /// (the mentioned regex is not internally used)
     ///
-    /// [`FORMATS`] is a slice of valid formats(msi, pkg, gz, and xz)
+    /// [`FormatKind::ALL`] contains the valid formats(msi, pkg, gz, and xz)
     ///
-    /// you can use [`Format::from_str`] if you want to convert a `&str`
+    /// you can use [`str::parse`] if you want to convert a `&str`
     /// to a Format, or [`Format::from_string_no_err`] if your application
     /// requires that there not be an error case.
     ///
@@ -122,135 +350,417 @@ impl Format {
     /// `dfsjj-ndfdsdf`)
     pub fn from_string(base_string: String) -> Result<Format, Error> {
         let split = base_string.split_once("-").unwrap_or((&base_string, ""));
-        let suffix = split.1.to_string();
-        let real_suffix = match suffix.as_str() {
-            "only" => Suffix::Only,
-            "" | "if-available" => Suffix::IfAvailable,
-            _ => return Err(anyhow!("invalid suffix {}", suffix)),
+        let suffix = match split.1 {
+            "" => Suffix::IfAvailable,
+            suffix => suffix.parse()?,
         };
-        if !FORMATS.contains(&split.0) {
-            return Err(anyhow!("invalid format {}", split.0));
-        }
         Ok(Format {
-            format: split.0.to_string(),
-            suffix: real_suffix,
+            format: split.0.parse()?,
+            suffix,
         })
     }
     /// see [`Format::from_string`] for the usage, this is virtually
     /// identical however doesn't return a `Result`.
     ///
     /// note that this method will report a value for any string(so anything
-    /// that matches `.*`) but will divide on the first occurance of `-`.
-    /// see the following code:
+    /// that matches `.*`) but will divide on the first occurance of `-`. if
+    /// the suffix is not "only", then it will output [`Suffix::IfAvailable`].
+    ///
+    /// since a [`Format`] can only hold a known [`FormatKind`], a format that
+    /// isn't one of [`FormatKind::ALL`] becomes [`FormatKind::Gz`], which is
+    /// available for every platform. see the following code:
     ///
     /// ```
-    /// # use rust_pkg_gen::{Format,Suffix};
-    /// let test_string = "test-ah-yes".to_string();
+    /// # use rust_pkg_gen::{Format,FormatKind,Suffix};
+    /// let test_string = "test-only".to_string();
     /// assert_eq!(Format::from_string_no_err(test_string),
     ///     Format {
+    ///         format: FormatKind::Gz,
+    ///         suffix: Suffix::Only
+    ///     }
+    /// );
+    /// ```
+    ///
+    /// use [`Format::parse_lenient`] to get the format as it was written.
+    ///
+    /// this method was originally used by `Format::deserialize`, which now
+    /// goes through the strict [`Format::from_string`] instead. it's kept
+    /// around for callers that explicitly want the lenient behavior.
+    pub fn from_string_no_err(base_string: String) -> Format {
+        let raw = Format::parse_lenient(&base_string);
+        Format {
+            format: raw.format.parse().unwrap_or(FormatKind::Gz),
+            suffix: raw.suffix,
+        }
+    }
+    /// Splits `base_string` into a format and a suffix like
+    /// [`Format::from_string_no_err`], but keeps the format as it was
+    /// written instead of requiring a known [`FormatKind`]. see the
+    /// following code:
+    ///
+    /// ```
+    /// # use rust_pkg_gen::{Format,RawFormat,Suffix};
+    /// assert_eq!(Format::parse_lenient("test-ah-yes"),
+    ///     RawFormat {
     ///         format: "test".to_string(),
     ///         suffix: Suffix::IfAvailable
     ///     }
     /// );
     /// ```
     ///
-    /// keep this in mind when using this method. thus the regex `([\w&&[^-]]+)((?:-.*)?)`
-    /// matches the output where capture group one is the format and capture group two
-    /// is the suffix. however, if the suffix is not "only", then it will output
-    /// [`Suffix::IfAvailable`].
+    /// thus the regex `([\w&&[^-]]+)((?:-.*)?)` matches the output where
+    /// capture group one is the format and capture group two is the suffix.
     ///
     /// THIS REGEX MAY OR MAY NOT BE KEPT UP-TO-DATE AS IT IS NOT USED INTERNALLY
-    ///
-    /// the reason that this method was implemented was to solve the problem
-    /// of constructing an error for Format::deserialize.
-    ///
-    /// I don't like that we had to implement it, but it's kind of necessary.
-    /// If you have any ideas, please please please open an issue or PR!
-    pub fn from_string_no_err(base_string: String) -> Format {
-        let split = base_string.split_once("-").unwrap_or((&base_string, ""));
-        let suffix = split.1.to_string();
-        let real_suffix = match suffix.as_str() {
+    pub fn parse_lenient(base_string: &str) -> RawFormat {
+        let split = base_string.split_once("-").unwrap_or((base_string, ""));
+        let real_suffix = match split.1 {
             "only" => Suffix::Only,
             _ => Suffix::IfAvailable,
         };
-        Format {
+        RawFormat {
             format: split.0.to_string(),
             suffix: real_suffix,
         }
     }
 }
 
+impl FromStr for Format {
+    type Err = Error;
+    /// Has the same semantic meaning as [`Format::from_string`].
+    fn from_str(s: &str) -> Result<Format, Error> {
+        Format::from_string(s.to_string())
+    }
+}
+
+impl fmt::Display for Format {
+    /// Writes the format the way it would appear in a config, always with its
+    /// suffix(so `gz` is written as `gz-if-available`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.format, self.suffix)
+    }
+}
+
+impl Serialize for Format {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
 impl<'de> Deserialize<'de> for Format {
     /// Deserialize this value from the given Serde deserializer.
     fn deserialize<D>(deserializer: D) -> Result<Format, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        Ok(Format::from_string_no_err(
-            deserializer.deserialize_str(StringVisitor)?,
-        ))
+        let base_string = deserializer.deserialize_str(StringVisitor)?;
+        Format::from_string(base_string.clone()).map_err(|err| {
+            serde::de::Error::custom(format!("invalid format `{}`: {}", base_string, err))
+        })
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(untagged)]
 /// A crate(used in [a rust config's crates value](RustConfigInner::crates)).
 ///
 /// Can be any valid value that can be put in a Cargo.toml's dependency section.
 ///
 /// Doesn't include the crate's name, which is assumed to be placed elsewhere.
+// boxing `Detailed` would make it a pain to construct and match on
+#[allow(clippy::large_enum_variant)]
 pub enum Crate {
     /// A basic version. What is generally seen in most Cargo.toml's.
     Version(String),
-    /// Detailed information about the dependency. Can include a version,
-    /// features, a path, and/or a git repository.
+    /// Several versions of the same crate, like `syn = ["1", "2"]`, so that
+    /// every one of them ends up in the local registry. Each entry can be
+    /// anything other than another list. See [`Crate::lower`].
+    ///
+    /// Comes before [`Crate::Detailed`] since that one can also be
+    /// deserialized from an array.
+    Versions(Vec<Crate>),
+    /// Detailed information about the dependency. Can include anything a
+    /// Cargo.toml's dependency table can, other than `workspace`.
     Detailed {
         /// The version. Generally a semver.
+        #[serde(skip_serializing_if = "Option::is_none")]
         version: Option<String>,
+        /// A semver range, like `>=1.0, <2`. Instead of just the version cargo
+        /// picks, every version matching it gets vendored. Has to be
+        /// [resolved](versions::resolve) before being written to a Cargo.toml.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        versions: Option<String>,
+        /// Only vendor the newest `latest-n` versions matching
+        /// [`versions`](Crate::Detailed::versions).
+        #[serde(rename = "latest-n", skip_serializing_if = "Option::is_none")]
+        latest_n: Option<usize>,
         /// The required features.
+        #[serde(skip_serializing_if = "Option::is_none")]
         features: Option<Vec<String>>,
+        /// Whether to enable the crate's default features. Defaults to `true`.
+        #[serde(
+            rename = "default-features",
+            alias = "default_features",
+            skip_serializing_if = "Option::is_none"
+        )]
+        default_features: Option<bool>,
+        /// Whether the dependency is optional.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        optional: Option<bool>,
+        /// The name of the crate, if it's different from the name it's
+        /// depended on as.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package: Option<String>,
+        /// The name of the registry to get the crate from, if it's not crates.io.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        registry: Option<String>,
         /// The path to the crate.
+        #[serde(skip_serializing_if = "Option::is_none")]
         path: Option<String>,
         /// The git repository of the crate.
+        #[serde(skip_serializing_if = "Option::is_none")]
         git: Option<String>,
+        /// The branch of the [git repository](Crate::Detailed::git) to use.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+        /// The tag of the [git repository](Crate::Detailed::git) to use.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        /// The revision of the [git repository](Crate::Detailed::git) to use.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+        /// Only use the crate on some platforms, like a Cargo.toml's
+        /// `[target.'cfg(windows)'.dependencies]`. Either a `cfg(...)`
+        /// expression or a target triple.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
     },
 }
 
 impl Crate {
-    /// Serializes the [`Crate`] to the standard format used in a Cargo.toml.
-    pub fn serialize(self) -> String {
-        if let Crate::Version(str) = self {
-            return format!("\"{}\"", str);
-        } else {
-            let Crate::Detailed {
-                version,
-                features,
-                path,
-                git,
-            } = self
-            else {
+    /// Converts the [`Crate`] to the value it has in a Cargo.toml's dependency
+    /// section: a string for a [`Crate::Version`], and an inline table for a
+    /// [`Crate::Detailed`]. A [`Crate::Versions`] becomes an array, which isn't
+    /// valid in a Cargo.toml, so it has to be [lowered](Crate::lower) first.
+    ///
+    /// The [`target`](Crate::Detailed::target) isn't included, since it
+    /// decides which table the crate goes in(see [`dependencies`]), and neither
+    /// are [`versions`](Crate::Detailed::versions) and
+    /// [`latest_n`](Crate::Detailed::latest_n), which aren't valid in a Cargo.toml.
+    pub fn to_toml(&self) -> toml_edit::Value {
+        if let Crate::Versions(versions) = self {
+            return toml_edit::Value::Array(versions.iter().map(Crate::to_toml).collect());
+        }
+        let Crate::Detailed {
+            version,
+            features,
+            default_features,
+            optional,
+            package,
+            registry,
+            path,
+            git,
+            branch,
+            tag,
+            rev,
+            versions: _,
+            latest_n: _,
+            target: _,
+        } = self
+        else {
+            let Crate::Version(version) = self else {
                 unreachable!();
             };
-            let mut out = String::new();
-            if let Some(version) = version {
-                out += &format!("version = {},", version);
-            }
-            if let Some(mut features) = features {
-                for ele in &mut features {
-                    *ele = format!("\"{}\"", ele);
-                }
-                out += &format!("features = [{}],", features.join(", "));
+            return version.into();
+        };
+        let mut table = toml_edit::InlineTable::new();
+        for (key, value) in [
+            ("version", version),
+            ("package", package),
+            ("registry", registry),
+            ("path", path),
+            ("git", git),
+            ("branch", branch),
+            ("tag", tag),
+            ("rev", rev),
+        ] {
+            if let Some(value) = value {
+                table.insert(key, value.into());
             }
-            if let Some(path) = path {
-                out += &format!("path = {},", path);
+        }
+        if let Some(features) = features {
+            table.insert("features", features.iter().collect());
+        }
+        if let Some(default_features) = default_features {
+            table.insert("default-features", (*default_features).into());
+        }
+        if let Some(optional) = optional {
+            table.insert("optional", (*optional).into());
+        }
+        table.into()
+    }
+
+    /// Serializes the [`Crate`] to the standard format used in a Cargo.toml.
+    /// See [`Crate::to_toml`].
+    pub fn serialize(self) -> String {
+        self.to_toml().to_string()
+    }
+
+    /// This crate without the entries whose [`target`](Crate::Detailed::target)
+    /// doesn't apply to any of `targets`, or `None` if there's nothing left.
+    /// See [`RustConfigInner::crates_for`].
+    pub fn for_targets(
+        &self,
+        targets: &[&String],
+        cfgs: &HashMap<String, TargetCfg>,
+    ) -> Option<Crate> {
+        match self {
+            Crate::Versions(versions) => {
+                let versions: Vec<Crate> = versions
+                    .iter()
+                    .filter_map(|krate| krate.for_targets(targets, cfgs))
+                    .collect();
+                (!versions.is_empty()).then_some(Crate::Versions(versions))
             }
-            if let Some(git) = git {
-                out += &format!("git = {},", git);
+            Crate::Detailed {
+                target: Some(target),
+                ..
+            } => {
+                let applies = match target.parse::<CfgExpr>() {
+                    Ok(expr) => targets
+                        .iter()
+                        .any(|t| cfgs.get(*t).is_none_or(|cfg| expr.matches(cfg))),
+                    Err(_) if target.starts_with("cfg(") => true,
+                    Err(_) => targets.contains(&target),
+                };
+                applies.then(|| self.clone())
             }
-            out = format!("{{ {} }}", out);
-            return out;
+            krate => Some(krate.clone()),
+        }
+    }
+
+    /// Whether this crate(or any of its entries) has a `cfg(...)`
+    /// [`target`](Crate::Detailed::target).
+    pub fn has_cfg_target(&self) -> bool {
+        match self {
+            Crate::Versions(versions) => versions.iter().any(Crate::has_cfg_target),
+            Crate::Detailed {
+                target: Some(target),
+                ..
+            } => target.starts_with("cfg("),
+            _ => false,
+        }
+    }
+
+    /// Turns the crate `name` into the dependencies it's written as in a
+    /// Cargo.toml. That's just itself, unless it's a [`Crate::Versions`], in
+    /// which case the first entry keeps the name and every other one is
+    /// renamed to `{name}-{index}`, with `package = "{name}"`.
+    pub fn lower(&self, name: &str) -> Vec<(String, Crate)> {
+        let Crate::Versions(versions) = self else {
+            return vec![(name.to_string(), self.clone())];
+        };
+        versions
+            .iter()
+            .enumerate()
+            .map(|(idx, krate)| {
+                if idx == 0 {
+                    return (name.to_string(), krate.clone());
+                }
+                let krate = match krate.clone() {
+                    Crate::Version(version) => Crate::Detailed {
+                        version: Some(version),
+                        versions: None,
+                        latest_n: None,
+                        features: None,
+                        default_features: None,
+                        optional: None,
+                        package: Some(name.to_string()),
+                        registry: None,
+                        path: None,
+                        git: None,
+                        branch: None,
+                        tag: None,
+                        rev: None,
+                        target: None,
+                    },
+                    Crate::Detailed {
+                        version,
+                        versions,
+                        latest_n,
+                        features,
+                        default_features,
+                        optional,
+                        package,
+                        registry,
+                        path,
+                        git,
+                        branch,
+                        tag,
+                        rev,
+                        target,
+                    } => Crate::Detailed {
+                        version,
+                        versions,
+                        latest_n,
+                        features,
+                        default_features,
+                        optional,
+                        package: package.or_else(|| Some(name.to_string())),
+                        registry,
+                        path,
+                        git,
+                        branch,
+                        tag,
+                        rev,
+                        target,
+                    },
+                    krate => krate,
+                };
+                (format!("{}-{}", name, idx), krate)
+            })
+            .collect()
+    }
+}
+
+/// Writes `crates` as the dependency tables of a Cargo.toml: `[dependencies]`,
+/// then a `[target.'...'.dependencies]` for each
+/// [`target`](Crate::Detailed::target) that's used(sorted), each with one
+/// `name = ...` line per crate, sorted by name. Crates with several versions
+/// are [lowered](Crate::lower) into one line per version.
+pub fn dependencies(crates: &HashMap<String, Crate>) -> String {
+    let mut document = toml_edit::DocumentMut::new();
+    document.insert("dependencies", toml_edit::table());
+    let mut targets = toml_edit::Table::new();
+    targets.set_implicit(true);
+    for (name, krate) in crates.iter().collect::<BTreeMap<_, _>>() {
+        for (name, krate) in krate.lower(name) {
+            let table = match &krate {
+                Crate::Detailed {
+                    target: Some(target),
+                    ..
+                } => {
+                    let platform = targets.entry(target).or_insert_with(|| {
+                        let mut platform = toml_edit::Table::new();
+                        platform.set_implicit(true);
+                        platform.insert("dependencies", toml_edit::table());
+                        toml_edit::Item::Table(platform)
+                    });
+                    &mut platform["dependencies"]
+                }
+                _ => &mut document["dependencies"],
+            };
+            table[name.as_str()] = toml_edit::Item::Value(krate.to_toml());
         }
     }
+    if !targets.is_empty() {
+        targets.sort_values();
+        document.insert("target", toml_edit::Item::Table(targets));
+    }
+    document.to_string()
 }
 
 /// Many crates. The key for the outer HashMap is
@@ -258,7 +768,7 @@ impl Crate {
 /// is a crate.
 pub type Crates = HashMap<String, HashMap<String, Crate>>;
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 /// The actual Rust config. Referred to simply by "Rust config"
 /// throughout this documentation. The entrypoint to deserializing
 /// a `rust-config.toml` file's individual configs.
@@ -266,18 +776,203 @@ pub struct RustConfigInner {
     /// A list of toolchains.
     pub toolchains: Vec<Toolchain>,
     /// A list of crates.
+    #[serde(serialize_with = "serialize_crates")]
     pub crates: Crates,
     /// A list of formats.
+    #[serde(serialize_with = "serialize_sorted")]
     pub formats: HashMap<String, Vec<Format>>,
+    /// Crates to [patch](patch), by crate ID like [`crates`](RustConfigInner::crates).
+    #[serde(
+        default,
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "serialize_crates"
+    )]
+    pub patches: Crates,
+}
+
+impl RustConfigInner {
+    /// The crates `toolchain` gets: the union of the crate lists of
+    /// [every one of its crate IDs](Toolchain::all_crate_ids), minus its
+    /// [excluded crates](Toolchain::exclude_crates). If a crate is in more than
+    /// one list, the first one wins(the [validator](validate) rejects that
+    /// unless they're the same). Unknown crate IDs are skipped.
+    ///
+    /// Crates with a [`target`](Crate::Detailed::target) are left out unless it
+    /// applies to one of the toolchain's platforms or targets, which should
+    /// already be [expanded](Toolchain::expanded): a target triple has to be
+    /// one of them, and a `cfg(...)` has to hold for one of them, going by
+    /// their cfg values in `cfgs`(see [`cfg::rustc_cfg`]). A `cfg(...)` is
+    /// assumed to hold for anything that isn't in `cfgs`.
+    pub fn crates_for(
+        &self,
+        toolchain: &Toolchain,
+        cfgs: &HashMap<String, TargetCfg>,
+    ) -> HashMap<String, Crate> {
+        let targets: Vec<&String> = toolchain
+            .platforms
+            .iter()
+            .chain(&toolchain.targets)
+            .collect();
+        let mut crates = HashMap::new();
+        for id in toolchain.all_crate_ids() {
+            for (name, krate) in self.crates.get(id).into_iter().flatten() {
+                if toolchain.exclude_crates.contains(name) || crates.contains_key(name) {
+                    continue;
+                }
+                if let Some(krate) = krate.for_targets(&targets, cfgs) {
+                    crates.insert(name.clone(), krate);
+                }
+            }
+        }
+        crates
+    }
+
+    /// The [patches](patch) `toolchain` gets: the union of the patches for
+    /// [every one of its crate IDs](Toolchain::all_crate_ids), where the first
+    /// one wins.
+    pub fn patches_for(&self, toolchain: &Toolchain) -> HashMap<String, Crate> {
+        let mut patches = HashMap::new();
+        for id in toolchain.all_crate_ids() {
+            for (name, krate) in self.patches.get(id).into_iter().flatten() {
+                patches.entry(name.clone()).or_insert_with(|| krate.clone());
+            }
+        }
+        patches
+    }
 }
 
 /// A Rust config file. The entrypoint to deserializing a
 /// `rust-config.toml` file.
 pub type RustConfig = HashMap<String, RustConfigInner>;
 
-/// Parse a `rust-config.toml` file. Simply reads a path and parses it as toml.
+/// Serializes a `HashMap` with its keys sorted so the output is deterministic.
+fn serialize_sorted<S, V>(map: &HashMap<String, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Like [`serialize_sorted`], but also sorts each crate set.
+fn serialize_crates<S>(crates: &Crates, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    crates
+        .iter()
+        .map(|(id, set)| (id, set.iter().collect::<BTreeMap<_, _>>()))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// Serializes a [`RustConfig`] back to TOML. Packages and all maps
+/// are sorted by key, and keys are written in kebab-case, so the output is
+/// the same for any two equivalent configs.
 ///
-/// Currently panics upon an error; May change in the future.
-pub fn parse_file(path: &Path) -> RustConfig {
-    toml::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+/// Doesn't preserve comments; see [`normalize::normalize`] for that.
+pub fn to_toml_string(config: &RustConfig) -> Result<String, Error> {
+    Ok(toml::to_string_pretty(
+        &config.iter().collect::<BTreeMap<_, _>>(),
+    )?)
+}
+
+#[derive(Debug, Clone, Default)]
+/// Options for loading a config with [`parse_file_with`] or [`parse_str_with`].
+pub struct LoadOptions {
+    /// Values for [`${VAR}`](interpolate), checked before the environment.
+    pub vars: HashMap<String, String>,
+    /// Overrides applied to the config, like `--set`.
+    pub overrides: Vec<interpolate::Override>,
+    /// Replace undefined variables with an empty string instead of failing.
+    /// Used by [`normalize`](normalize::normalize), which keeps `${VAR}`s as
+    /// written and doesn't need the values.
+    pub allow_undefined: bool,
+}
+
+impl LoadOptions {
+    /// Looks up the variable `name` in [`vars`](LoadOptions::vars), then in the
+    /// environment.
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+    }
+}
+
+/// Parse a `rust-config.toml` file. Simply reads a path and parses it as toml,
+/// along with any files it [includes](include).
+///
+/// Returns a [`ConfigError`] pointing at the offending value if the file can't
+/// be read or isn't a valid config.
+pub fn parse_file(path: &Path) -> Result<RustConfig, ConfigError> {
+    parse_file_with(path, &LoadOptions::default())
+}
+
+/// Same as [`parse_file`], but with [`LoadOptions`].
+pub fn parse_file_with(path: &Path, options: &LoadOptions) -> Result<RustConfig, ConfigError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| ConfigError::new(path, format!("failed to read file: {}", err)))?;
+    parse_str_with(&contents, path, options)
+}
+
+/// Parse the contents of a `rust-config.toml` file. `path` is used for error
+/// reporting, and [included](include) files are found relative to it. If
+/// `path` is a `Cargo.toml`, the config is read from its [metadata](metadata).
+///
+/// Variables are [interpolated](interpolate) and includes and
+/// [inheritance](inherit) are resolved before the config is deserialized.
+pub fn parse_str(contents: &str, path: &Path) -> Result<RustConfig, ConfigError> {
+    parse_str_with(contents, path, &LoadOptions::default())
+}
+
+/// Same as [`parse_str`], but with [`LoadOptions`].
+pub fn parse_str_with(
+    contents: &str,
+    path: &Path,
+    options: &LoadOptions,
+) -> Result<RustConfig, ConfigError> {
+    let mut table: toml::Table =
+        toml::from_str(contents).map_err(|err| ConfigError::from_toml(path, contents, err))?;
+    let metadata = if metadata::is_manifest(path) {
+        let (config, metadata) = metadata::from_manifest(&table, path, contents)?;
+        table = config;
+        Some(metadata)
+    } else {
+        None
+    };
+    let error = |key: &[error::KeySegment], message: String| {
+        let key = match &metadata {
+            Some(metadata) => metadata.file_key(key),
+            None => key.to_vec(),
+        };
+        ConfigError::at(path, contents, &key, message)
+    };
+
+    interpolate::interpolate(&mut table, options).map_err(|err| error(&err.key, err.message))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for (_, package) in table.iter_mut() {
+        if let Some(patches) = package.get_mut("patches") {
+            patch::absolute_paths(patches, dir);
+        }
+    }
+    let includes = include::resolve(&mut table, path, contents, options, metadata.as_ref())?;
+    for over in &options.overrides {
+        over.apply(&mut table).map_err(|err| {
+            ConfigError::new(
+                path,
+                format!("--set {}: `{}` {}", over, format_key(&err.key), err.message),
+            )
+        })?;
+    }
+    inherit::resolve(&mut table).map_err(|err| error(&err.key, err.message))?;
+    serde_path_to_error::deserialize(toml::Value::Table(table)).map_err(|err| {
+        let key = error::path_to_key(err.path());
+        let message = err.into_inner().message().trim_end().to_string();
+        match includes.locate(&key) {
+            Some((file, key)) => ConfigError::at(&file.path, &file.contents, &key, message),
+            None => error(&key, message),
+        }
+    })
 }
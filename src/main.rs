@@ -4,14 +4,17 @@
 //! but that will be changed eventually as a non-breaking change.
 
 use chrono::prelude::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use core::str;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 use log::*;
 use rand::{Rng, SeedableRng};
+use rust_pkg_gen::channel::{ResolvedToolchain, ToolchainSpec};
 use rust_pkg_gen::resources::{InstallAssets, TemplateAssets};
+use rust_pkg_gen::FormatKind;
 use std::{
+    collections::{BTreeMap, HashMap},
     fs::{self, write, File},
     path::{Path, PathBuf},
     process::{self, Stdio},
@@ -24,6 +27,9 @@ fn gen_char() -> u8 {
 #[derive(Parser, Debug)]
 #[command(version)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[cfg(not(debug_assertions))]
     #[arg(long = "temp-dir")]
     temp_dir: Option<PathBuf>,
@@ -81,6 +87,123 @@ struct Cli {
         help = "Disable running the build.sh file for crates(mostly used for debugging)"
     )]
     no_build_crates: bool,
+    #[arg(
+        long = "set",
+        value_name = "KEY=VALUE",
+        global = true,
+        help = "Overrides a key in the configuration file, like pkg.toolchains[0].channel=beta. Can be used multiple times"
+    )]
+    set: Vec<rust_pkg_gen::interpolate::Override>,
+    #[arg(
+        short = 'p',
+        long = "package",
+        value_name = "NAME",
+        global = true,
+        help = "Only builds(or lists) packages matching this name, which can be a glob. Can be used multiple times"
+    )]
+    packages: Vec<String>,
+    #[arg(
+        short = 't',
+        long = "toolchain",
+        value_name = "INDEX|CHANNEL",
+        global = true,
+        help = "Only builds(or lists) toolchains with this index in their package or this channel. Can be used multiple times"
+    )]
+    toolchains: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(
+        about = "Checks a configuration file for mistakes without building anything(doesn't access the network)"
+    )]
+    Validate {
+        #[arg(
+            default_value = "rust-config.toml",
+            help = "The path to the configuration file"
+        )]
+        path: PathBuf,
+    },
+    #[command(
+        about = "Normalizes a configuration file(sorted packages and maps, keys in a fixed order and in kebab-case, expanded tables), keeping comments, [defaults], extends, include, and ${VAR}s"
+    )]
+    Fmt {
+        #[arg(
+            default_value = "rust-config.toml",
+            help = "The path to the configuration file"
+        )]
+        path: PathBuf,
+        #[arg(
+            long = "check",
+            default_value_t = false,
+            help = "Don't write anything, just exit with an error if the file isn't normalized"
+        )]
+        check: bool,
+        #[arg(
+            long = "expanded",
+            default_value_t = false,
+            conflicts_with = "check",
+            help = "Print the config with [defaults], extends, include, and ${VAR}s expanded instead of writing anything"
+        )]
+        expanded: bool,
+    },
+    #[command(
+        about = "Creates a package from an existing project's rust-toolchain.toml and Cargo.lock and adds it to a configuration file"
+    )]
+    Init {
+        #[arg(long = "from", help = "The path to the project")]
+        from: PathBuf,
+        #[arg(
+            long = "host",
+            help = "The platform to build the package for. Defaults to the current one"
+        )]
+        host: Option<String>,
+        #[arg(
+            default_value = "rust-config.toml",
+            help = "The path to the configuration file. Created if it doesn't exist"
+        )]
+        path: PathBuf,
+    },
+    #[command(
+        about = "Interactively creates a package, using what's available in the selected channel, and adds it to a configuration file"
+    )]
+    New {
+        #[arg(
+            default_value = "rust-config.toml",
+            help = "The path to the configuration file. Created if it doesn't exist"
+        )]
+        path: PathBuf,
+    },
+    #[command(about = "Lists the packages and toolchains in a configuration file")]
+    List {
+        #[arg(
+            default_value = "rust-config.toml",
+            help = "The path to the configuration file"
+        )]
+        path: PathBuf,
+    },
+    #[command(
+        about = "Lists the targets in a channel's manifest, with the components available for each"
+    )]
+    Targets {
+        #[arg(
+            long = "channel",
+            default_value = "stable",
+            help = "The channel, like stable, 1.83.0, or nightly-2024-11-30"
+        )]
+        channel: String,
+    },
+    #[command(
+        about = "Prints a JSON Schema for configuration files, for editors to validate and autocomplete them with"
+    )]
+    Schema {
+        #[arg(
+            long = "output",
+            short = 'o',
+            help = "Write the schema to this file instead of printing it"
+        )]
+        output: Option<PathBuf>,
+    },
 }
 
 fn move_files_in_directory(src_dir: &str, dest_dir: &str) -> std::io::Result<()> {
@@ -106,15 +229,440 @@ fn move_files_in_directory(src_dir: &str, dest_dir: &str) -> std::io::Result<()>
     Ok(())
 }
 
-fn generate_crates(
-    cfg: &rust_pkg_gen::RustConfigInner,
+/// Parses and validates the config at `path` with the `--set` overrides,
+/// printing every problem and exiting if there are any.
+fn load_config(
+    path: &Path,
+    overrides: &[rust_pkg_gen::interpolate::Override],
+) -> rust_pkg_gen::RustConfig {
+    let data = parse_config(path, overrides);
+    check_config(path, rust_pkg_gen::validate::validate(&data));
+    data
+}
+
+/// Parses the config at `path` with the `--set` overrides, exiting if it
+/// can't be parsed.
+fn parse_config(
+    path: &Path,
+    overrides: &[rust_pkg_gen::interpolate::Override],
+) -> rust_pkg_gen::RustConfig {
+    let options = rust_pkg_gen::LoadOptions {
+        overrides: overrides.to_vec(),
+        ..Default::default()
+    };
+    match rust_pkg_gen::parse_file_with(path, &options) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("{}", err.render());
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints every problem found in the config at `path` and exits if there
+/// are any.
+fn check_config(path: &Path, errors: Vec<rust_pkg_gen::validate::ValidationError>) {
+    if !errors.is_empty() {
+        for err in &errors {
+            eprintln!("error: {}", err);
+        }
+        eprintln!("{} problem(s) found in {}", errors.len(), path.display());
+        process::exit(1);
+    }
+}
+
+/// Downloads the manifest of every channel used by `selected`, keyed by the
+/// channel as written. Channels whose manifest can't be downloaded are left
+/// out, so their targets are checked against every known target instead.
+fn fetch_manifests(
+    selected: &[rust_pkg_gen::select::Selected],
+    quiet: bool,
+) -> HashMap<String, toml::Value> {
+    use rust_pkg_gen::{channel, copied::DEFAULT_UPSTREAM_URL};
+
+    let mut manifests = HashMap::new();
+    for toolchain in selected.iter().flat_map(|selected| selected.toolchains()) {
+        if manifests.contains_key(&toolchain.channel) {
+            continue;
+        }
+        // an invalid channel is reported by check_config
+        let Ok(spec) = toolchain.channel.parse::<ToolchainSpec>() else {
+            continue;
+        };
+        match channel::fetch_manifest(DEFAULT_UPSTREAM_URL, &spec) {
+            Ok(manifest) => {
+                manifests.insert(toolchain.channel.clone(), manifest);
+            }
+            Err(err) => {
+                if !quiet {
+                    warn!(
+                        "couldn't download the manifest for {}, so its targets are checked against every known target: {}",
+                        spec, err
+                    );
+                }
+            }
+        }
+    }
+    manifests
+}
+
+/// Writes `items` as the inside of a TOML array of strings.
+fn quoted_list(items: &[String]) -> String {
+    items
+        .iter()
+        .map(|item| format!("\"{}\"", item))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Writes the components of each platform as the arms of the `case` in
+/// install.sh that picks the ones for the platform it's running on.
+fn components_cases(components: &BTreeMap<String, Vec<String>>) -> String {
+    components
+        .iter()
+        .map(|(platform, components)| {
+            format!(
+                "    {}) COMPONENTS=\"{}\" ;;",
+                platform,
+                components.join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes the components of each platform as the entries of the hashtable in
+/// install.ps1 that the ones for the platform it's running on are taken from.
+fn components_table(components: &BTreeMap<String, Vec<String>>) -> String {
+    components
+        .iter()
+        .map(|(platform, components)| {
+            format!("    \"{}\" = \"{}\"", platform, components.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Asks rustc for the cfg values of every platform and target of `toolchain`,
+/// if any of the crates it gets have a `cfg(...)` target. Targets rustc can't
+/// print the cfg of are left out, so every `cfg(...)` holds for them.
+fn target_cfgs(
+    package: &rust_pkg_gen::RustConfigInner,
     toolchain: &rust_pkg_gen::Toolchain,
-) -> String {
-    let mut out: String = String::new();
-    for (crte, version) in cfg.crates.get(&toolchain.crate_id).unwrap() {
-        out = format!("{}{} = {}\n", out, crte, version.clone().serialize()).to_string();
+    quiet: bool,
+) -> HashMap<String, rust_pkg_gen::cfg::TargetCfg> {
+    let mut cfgs = HashMap::new();
+    if !package
+        .crates_for(toolchain, &cfgs)
+        .values()
+        .any(rust_pkg_gen::Crate::has_cfg_target)
+    {
+        return cfgs;
+    }
+    for target in toolchain.platforms.iter().chain(&toolchain.targets) {
+        match rust_pkg_gen::cfg::rustc_cfg(target) {
+            Ok(cfg) => {
+                cfgs.insert(target.clone(), cfg);
+            }
+            Err(err) => {
+                if !quiet {
+                    warn!("{}; keeping every crate with a cfg(...) target", err);
+                }
+            }
+        }
+    }
+    cfgs
+}
+
+/// Expands `host`, aliases, and globs in the platforms and targets of `toolchain`
+/// against the targets its channel has `rust-std` for, from `known_targets`,
+/// or every known target if they aren't known. Exits if a pattern doesn't
+/// match anything.
+fn expand_targets(
+    toolchain: &rust_pkg_gen::Toolchain,
+    known_targets: &HashMap<String, Vec<String>>,
+    quiet: bool,
+) -> rust_pkg_gen::Toolchain {
+    use rust_pkg_gen::targets;
+
+    if !toolchain
+        .platforms
+        .iter()
+        .chain(&toolchain.targets)
+        .any(|target| targets::is_pattern(target))
+    {
+        return toolchain.clone();
+    }
+    let expanded = match known_targets.get(&toolchain.channel) {
+        Some(known) => toolchain.expanded(known),
+        None => toolchain.expanded(&targets::TARGETS),
+    };
+    match expanded {
+        Ok(expanded) => {
+            if !quiet {
+                info!(
+                    "Expanded the platforms of {} to {} and its targets to {}",
+                    toolchain.channel,
+                    expanded.platforms.join(", "),
+                    expanded.targets.join(", ")
+                );
+            }
+            expanded
+        }
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Picks the packages and toolchains selected with `--package` and
+/// `--toolchain` from `data`, exiting if a filter doesn't match anything.
+fn select_packages(
+    data: rust_pkg_gen::RustConfig,
+    args: &Cli,
+) -> Vec<rust_pkg_gen::select::Selected> {
+    let selection = rust_pkg_gen::select::Selection {
+        packages: args.packages.clone(),
+        toolchains: args.toolchains.clone(),
+    };
+    match rust_pkg_gen::select::select(data, &selection) {
+        Ok(selected) => selected,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Reads the config at `path`, which is empty if it doesn't exist yet.
+fn read_existing(path: &Path) -> Result<(String, toml::Table), String> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(format!("failed to read {}: {}", path.display(), err)),
+    };
+    let config = toml::from_str(&contents).map_err(|err| {
+        rust_pkg_gen::error::ConfigError::from_toml(path, &contents, err).render()
+    })?;
+    Ok((contents, config))
+}
+
+/// Whether the config at `path` already has a package called `name`.
+fn has_package(path: &Path, name: &str) -> Result<bool, String> {
+    read_existing(path).map(|(_, config)| config.contains_key(name))
+}
+
+/// Adds `package` to the end of the config at `path`, creating it if it
+/// doesn't exist. Exits if there's already a package called `name`, or if the
+/// config can't be read.
+fn add_package(path: &Path, name: String, package: rust_pkg_gen::RustConfigInner, quiet: bool) {
+    let (contents, existing) = match read_existing(path) {
+        Ok(existing) => existing,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if existing.contains_key(&name) {
+        eprintln!("{} already has a package named {}", path.display(), name);
+        process::exit(1);
+    }
+    let config = rust_pkg_gen::RustConfig::from([(name.clone(), package)]);
+    let mut out = contents.trim_end().to_string();
+    if !out.is_empty() {
+        out += "\n\n";
+    }
+    out += &rust_pkg_gen::to_toml_string(&config).unwrap();
+    write(path, out).unwrap();
+    if !quiet {
+        println!("Added package {} to {}", name, path.display());
+    }
+}
+
+/// Asks for every part of a new package, offering only what's available in
+/// the channel manifest of the selected channel.
+fn new_package(path: &Path) -> (String, rust_pkg_gen::RustConfigInner) {
+    use dialoguer::{Confirm, Input, MultiSelect, Select};
+    use rust_pkg_gen::{channel, Crate, Format, Suffix};
+
+    let name: String = Input::new()
+        .with_prompt("Package name")
+        .validate_with(|name: &String| match has_package(path, name) {
+            Ok(true) => Err(format!(
+                "{} already has a package named {}",
+                path.display(),
+                name
+            )),
+            Ok(false) => Ok(()),
+            Err(err) => Err(err),
+        })
+        .interact_text()
+        .unwrap();
+    let spec: ToolchainSpec = Input::<String>::new()
+        .with_prompt("Channel(stable, beta, nightly, a version, or a dated channel)")
+        .default("stable".to_string())
+        .validate_with(|channel: &String| channel.parse::<ToolchainSpec>().map(|_| ()))
+        .interact_text()
+        .unwrap()
+        .parse()
+        .unwrap();
+
+    info!("Downloading the manifest for {}...", spec);
+    let manifest = match channel::fetch_manifest(rust_pkg_gen::copied::DEFAULT_UPSTREAM_URL, &spec)
+    {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Ok(resolved) = ResolvedToolchain::from_manifest(&spec, &manifest) {
+        println!("{} is rust {}", spec, resolved.version);
+    }
+
+    let all_platforms = channel::available_targets(&manifest, "rustc");
+    let host = rust_pkg_gen::targets::host_triple();
+    let platforms: Vec<String> = loop {
+        let defaults: Vec<bool> = all_platforms
+            .iter()
+            .map(|platform| Some(platform) == host.as_ref())
+            .collect();
+        let selected = MultiSelect::new()
+            .with_prompt("Platforms to install the toolchain on(space to select)")
+            .items(&all_platforms)
+            .defaults(&defaults)
+            .interact()
+            .unwrap();
+        if !selected.is_empty() {
+            break selected
+                .into_iter()
+                .map(|i| all_platforms[i].clone())
+                .collect();
+        }
+        println!("Select at least one platform.");
+    };
+
+    let all_targets: Vec<String> = channel::available_targets(&manifest, "rust-std")
+        .into_iter()
+        .filter(|target| !platforms.contains(target))
+        .collect();
+    let targets: Vec<String> = MultiSelect::new()
+        .with_prompt("Extra targets to build for(space to select)")
+        .items(&all_targets)
+        .interact()
+        .unwrap()
+        .into_iter()
+        .map(|i| all_targets[i].clone())
+        .collect();
+
+    let all_components = channel::available_components(&manifest, &platforms);
+    let defaults: Vec<bool> = all_components
+        .iter()
+        .map(|component| rust_pkg_gen::init::DEFAULT_COMPONENTS.contains(&component.as_str()))
+        .collect();
+    let components: Vec<String> = MultiSelect::new()
+        .with_prompt("Components(space to select)")
+        .items(&all_components)
+        .defaults(&defaults)
+        .interact()
+        .unwrap()
+        .into_iter()
+        .map(|i| all_components[i].clone())
+        .collect();
+
+    let mut formats = HashMap::new();
+    for platform in &platforms {
+        let mut kinds = vec![FormatKind::Gz, FormatKind::Xz];
+        if platform.contains("windows") {
+            kinds.insert(0, FormatKind::Msi);
+        } else if platform.contains("apple") {
+            kinds.insert(0, FormatKind::Pkg);
+        }
+        let selected = loop {
+            let selected = MultiSelect::new()
+                .with_prompt(format!("Formats for {}(space to select)", platform))
+                .items(&kinds)
+                .defaults(&[true])
+                .interact()
+                .unwrap();
+            if !selected.is_empty() {
+                break selected;
+            }
+            println!("Select at least one format.");
+        };
+        let only = Confirm::new()
+            .with_prompt(format!(
+                "Fail if {} isn't available for {}?",
+                kinds[selected[0]], platform
+            ))
+            .default(false)
+            .interact()
+            .unwrap();
+        let list: Vec<Format> = selected
+            .into_iter()
+            .enumerate()
+            .map(|(idx, i)| Format {
+                format: kinds[i].clone(),
+                suffix: if idx == 0 && only {
+                    Suffix::Only
+                } else {
+                    Suffix::IfAvailable
+                },
+            })
+            .collect();
+        formats.insert(platform.clone(), list);
     }
-    out
+
+    let mut crates = HashMap::new();
+    loop {
+        let input: String = Input::new()
+            .with_prompt("Crate to include, as name or name@version(empty to finish)")
+            .allow_empty(true)
+            .interact_text()
+            .unwrap();
+        let input = input.trim();
+        if input.is_empty() {
+            break;
+        }
+        let (crate_name, version) = input.split_once('@').unwrap_or((input, "*"));
+        crates.insert(crate_name.to_string(), Crate::Version(version.to_string()));
+    }
+    if crates.is_empty()
+        && Select::new()
+            .with_prompt("No crates were added")
+            .items(&["Continue without crates", "Abort"])
+            .default(0)
+            .interact()
+            .unwrap()
+            == 1
+    {
+        process::exit(1);
+    }
+
+    #[allow(deprecated)]
+    let toolchain = rust_pkg_gen::Toolchain {
+        edition: None,
+        channel: spec.to_string(),
+        profile: None,
+        components,
+        crate_id: name.clone(),
+        crate_ids: Vec::new(),
+        exclude_crates: Vec::new(),
+        format_map: platforms.iter().map(|p| (p.clone(), p.clone())).collect(),
+        platforms,
+        targets,
+    };
+    let package = rust_pkg_gen::RustConfigInner {
+        toolchains: vec![toolchain],
+        crates: HashMap::from([(name.clone(), crates)]),
+        formats,
+        patches: HashMap::new(),
+    };
+    for err in rust_pkg_gen::validate::validate_package(&name, &package) {
+        eprintln!("warning: {}", err);
+    }
+    (name, package)
 }
 
 fn main() {
@@ -128,11 +676,169 @@ fn main() {
         env_logger::init();
     }
 
-    if !args.quiet {
+    // the schema and the lists can be printed to stdout, so they have to be
+    // the only thing there
+    let printing_output = matches!(
+        args.command,
+        Some(
+            Command::Schema { output: None }
+                | Command::Fmt { expanded: true, .. }
+                | Command::List { .. }
+                | Command::Targets { .. }
+        )
+    );
+    if !args.quiet && !printing_output {
         println!("rust-pkg-gen v{}", env!("CARGO_PKG_VERSION"))
     }
 
-    let path = &PathBuf::from(args.path);
+    match &args.command {
+        Some(Command::Validate { path }) => {
+            load_config(path, &args.set);
+            if !args.quiet {
+                println!("{} is valid", path.display());
+            }
+            return;
+        }
+        Some(Command::Fmt {
+            path,
+            check,
+            expanded,
+        }) => {
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    eprintln!("failed to read {}: {}", path.display(), err);
+                    process::exit(1);
+                }
+            };
+            let normalized = if *expanded {
+                rust_pkg_gen::normalize::expanded(&contents, path)
+            } else {
+                rust_pkg_gen::normalize::normalize(&contents, path)
+            };
+            let normalized = match normalized {
+                Ok(normalized) => normalized,
+                Err(err) => {
+                    eprintln!("{}", err.render());
+                    process::exit(1);
+                }
+            };
+            if *expanded {
+                print!("{}", normalized);
+            } else if *check {
+                if normalized != contents {
+                    eprintln!("{} isn't normalized", path.display());
+                    process::exit(1);
+                }
+            } else if normalized != contents {
+                write(path, normalized).unwrap();
+                if !args.quiet {
+                    println!("Normalized {}", path.display());
+                }
+            }
+            return;
+        }
+        Some(Command::Init { from, host, path }) => {
+            let Some(host) = host.clone().or_else(rust_pkg_gen::targets::host_triple) else {
+                eprintln!("couldn't detect the current platform, pass it with --host");
+                process::exit(1);
+            };
+            let (name, package) = match rust_pkg_gen::init::package_from_project(from, &host) {
+                Ok(package) => package,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            };
+            add_package(path, name, package, args.quiet);
+            return;
+        }
+        Some(Command::New { path }) => {
+            if let Err(err) = read_existing(path) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+            let (name, package) = new_package(path);
+            add_package(path, name, package, args.quiet);
+            return;
+        }
+        Some(Command::List { path }) => {
+            let selected = select_packages(load_config(path, &args.set), &args);
+            print!("{}", rust_pkg_gen::select::list(&selected));
+            return;
+        }
+        Some(Command::Targets { channel }) => {
+            use rust_pkg_gen::{channel, copied::DEFAULT_UPSTREAM_URL};
+
+            let spec = match channel.parse::<ToolchainSpec>() {
+                Ok(spec) => spec,
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            };
+            let manifest = match channel::fetch_manifest(DEFAULT_UPSTREAM_URL, &spec) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    error!("{}", err);
+                    process::exit(1);
+                }
+            };
+            for (target, components) in channel::components_by_target(&manifest) {
+                println!("{}: {}", target, components.join(", "));
+            }
+            return;
+        }
+        Some(Command::Schema { output }) => {
+            let schema = rust_pkg_gen::schema::to_json_string();
+            match output {
+                Some(output) => {
+                    if let Err(err) = fs::write(output, schema) {
+                        error!("failed to write {}: {}", output.display(), err);
+                        process::exit(1);
+                    }
+                }
+                None => print!("{}", schema),
+            }
+            return;
+        }
+        None => {}
+    }
+
+    // the targets are checked against the manifests of the channels being
+    // built, so targets newer than `targets::TARGETS` work too. Without a
+    // toolchain download nothing is fetched and every known target is used.
+    // Only the selected packages are validated, so a broken package doesn't
+    // stop the others from being built.
+    let data = parse_config(&args.path, &args.set);
+    let selected = select_packages(data, &args);
+    let manifests = if args.no_download_toolchain {
+        HashMap::new()
+    } else {
+        fetch_manifests(&selected, args.quiet)
+    };
+    let known_targets: HashMap<String, Vec<String>> = manifests
+        .iter()
+        .map(|(channel, manifest)| {
+            (
+                channel.clone(),
+                rust_pkg_gen::channel::available_targets(manifest, "rust-std"),
+            )
+        })
+        .collect();
+    check_config(
+        &args.path,
+        selected
+            .iter()
+            .flat_map(|selected| {
+                rust_pkg_gen::validate::validate_package_with_targets(
+                    &selected.name,
+                    &selected.package,
+                    &known_targets,
+                )
+            })
+            .collect(),
+    );
 
     let chars: &[u8; 6] = &[
         gen_char(),
@@ -142,8 +848,8 @@ fn main() {
         gen_char(),
         gen_char(),
     ];
-    let dir = if args.temp_dir.is_some() {
-        if args.temp_dir.clone().unwrap().exists() && !args.overwrite {
+    let dir = if let Some(temp_dir) = args.temp_dir {
+        if temp_dir.exists() && !args.overwrite {
             let confirmation = dialoguer::Confirm::new()
                 .with_prompt("Temporary directory already exists, overwrite?")
                 .default(false)
@@ -158,22 +864,21 @@ fn main() {
                 if !args.quiet {
                     info!("Overwriting.");
                 }
-                std::fs::remove_dir_all(args.temp_dir.clone().unwrap()).unwrap();
+                std::fs::remove_dir_all(&temp_dir).unwrap();
             }
         }
-        if args.overwrite {
-            std::fs::remove_dir_all(args.temp_dir.clone().unwrap()).unwrap();
+        if args.overwrite && temp_dir.exists() {
+            std::fs::remove_dir_all(&temp_dir).unwrap();
         }
-        args.temp_dir.unwrap()
+        temp_dir
     } else {
         std::env::temp_dir().join(PathBuf::from(String::from_utf8_lossy(chars).as_ref()))
     };
 
-    let data = rust_pkg_gen::parse_file(path);
-
-    for (item, cfg) in data {
-        let dir = dir.join(item);
-        for toolchain in &cfg.toolchains {
+    for selected in &selected {
+        let cfg = &selected.package;
+        let dir = dir.join(&selected.name);
+        for toolchain in selected.toolchains() {
             let chars: &[u8; 6] = &[
                 gen_char(),
                 gen_char(),
@@ -183,6 +888,28 @@ fn main() {
                 gen_char(),
             ];
             let dir = dir.join(str::from_utf8(chars).unwrap());
+            let toolchain = &expand_targets(toolchain, &known_targets, args.quiet);
+            let patches = cfg.patches_for(toolchain);
+            let cfgs = target_cfgs(cfg, toolchain, args.quiet);
+            let mut crates = cfg.crates_for(toolchain, &cfgs);
+            // without building, there's no need to look up which versions exist
+            let older_versions = if args.no_build_crates {
+                rust_pkg_gen::versions::keep_ranges(&mut crates);
+                Vec::new()
+            } else {
+                match rust_pkg_gen::versions::resolve(&mut crates, |name| {
+                    rust_pkg_gen::versions::fetch_index(
+                        rust_pkg_gen::versions::CRATES_IO_INDEX,
+                        name,
+                    )
+                }) {
+                    Ok(older_versions) => older_versions,
+                    Err(err) => {
+                        error!("{}", err);
+                        process::exit(1);
+                    }
+                }
+            };
             for ele in TemplateAssets::iter() {
                 let file = TemplateAssets::get(&ele).unwrap();
 
@@ -199,17 +926,38 @@ fn main() {
                     std::str::from_utf8(file.data.as_ref())
                         .unwrap()
                         .replace("{?TOOLCHAIN.CHANNEL}", &toolchain.channel)
+                        .replace("{?TOOLCHAIN.TARGETS}", &quoted_list(&toolchain.targets))
                         .replace(
-                            "{?TOOLCHAIN.TARGETS}",
-                            &("\"".to_owned() + &toolchain.targets.join("\",\"") + "\""),
+                            "{?TOOLCHAIN.PROFILE}",
+                            &toolchain
+                                .profile
+                                .as_ref()
+                                .map(|profile| format!("profile = \"{}\"\n", profile))
+                                .unwrap_or_default(),
                         )
                         .replace(
                             "{?TOOLCHAIN.COMPONENTS}",
-                            &("\"".to_owned() + &toolchain.components.join("\",\"") + "\""),
+                            &quoted_list(&toolchain.components),
                         )
-                        .replace("{?CRATES}", &generate_crates(&(cfg.clone()), &toolchain)),
+                        .replace("{?CRATES}", &rust_pkg_gen::dependencies(&crates))
+                        .replace("{?PATCHES}", &rust_pkg_gen::patch::manifest_table(&patches)),
+                )
+                .unwrap();
+            }
+            // every other version of a crate with `versions` gets its own
+            // project, which build.sh syncs into the registry too
+            for (idx, (name, krate)) in older_versions.iter().enumerate() {
+                let project = dir
+                    .join(PathBuf::from("crates"))
+                    .join(PathBuf::from("versions"))
+                    .join(PathBuf::from(idx.to_string()));
+                std::fs::create_dir_all(project.join(PathBuf::from("src"))).unwrap();
+                write(
+                    project.join(PathBuf::from("Cargo.toml")),
+                    rust_pkg_gen::versions::manifest(name, krate),
                 )
                 .unwrap();
+                write(project.join(PathBuf::from("src")).join("lib.rs"), "").unwrap();
             }
 
             if !args.no_build_crates {
@@ -232,17 +980,28 @@ fn main() {
                     build = build.arg("save")
                 }
                 build.spawn().unwrap().wait().unwrap();
+
+                if let Err(err) = rust_pkg_gen::patch::vendor(
+                    &patches,
+                    &dir.join(PathBuf::from("crates")),
+                    args.quiet,
+                ) {
+                    error!("{}", err);
+                    process::exit(1);
+                }
             }
             write(
                 dir.join(PathBuf::from("crates"))
                     .join(PathBuf::from("README.md")),
-                rust_pkg_gen::resources::CRATES_README.replace(
-                    "{?TOOLCHAIN.CRATES_DIR}",
-                    fs::canonicalize(dir.join(PathBuf::from("crates")))
-                        .unwrap()
-                        .to_str()
-                        .unwrap(),
-                ),
+                (rust_pkg_gen::resources::CRATES_README.to_string()
+                    + &rust_pkg_gen::patch::readme(&patches))
+                    .replace(
+                        "{?TOOLCHAIN.CRATES_DIR}",
+                        fs::canonicalize(dir.join(PathBuf::from("crates")))
+                            .unwrap()
+                            .to_str()
+                            .unwrap(),
+                    ),
             )
             .unwrap();
 
@@ -254,13 +1013,18 @@ fn main() {
                     toolchain.targets.iter().map(|s| &**s).collect(),
                     dir.join("toolchain").to_str().unwrap(),
                     toolchain.components.iter().map(|s| &**s).collect(),
+                    toolchain.profile.as_deref(),
                     toolchain.platforms.iter().map(|s| &**s).collect(),
                     args.quiet,
                     toolchain
-                        .format_map
+                        .platforms
                         .iter()
-                        .map(|(k, v)| (k.as_str(), cfg.formats[v].clone()))
+                        .filter_map(|platform| {
+                            let id = toolchain.format_for(platform)?;
+                            Some((platform.as_str(), cfg.formats[id].clone()))
+                        })
                         .collect(),
+                    &manifests,
                 ) {
                     error!("{}", err);
                     process::exit(1);
@@ -271,14 +1035,63 @@ fn main() {
                 fs::remove_dir_all(dir.join("tmp").to_str().unwrap()).unwrap();
             }
 
+            // the names the install scripts use for the components of each
+            // platform, with the ones in the profile, only known once the
+            // manifest is downloaded too
+            let mut components: BTreeMap<String, Vec<String>> = toolchain
+                .platforms
+                .iter()
+                .map(|platform| (platform.clone(), toolchain.components.clone()))
+                .collect();
+            // the channel used in the names of the toolchain's files(see
+            // `ResolvedToolchain::artifact_channel`), only known once the manifest is downloaded
+            let mut artifact_channel = toolchain.channel.clone();
+
             if !args.no_download_toolchain {
-                let dist_data_path = dir
-                    .join("toolchain")
-                    .join(format!("dist/channel-rust-{}.toml", toolchain.channel));
+                // validated by load_config
+                let spec = toolchain.channel.parse::<ToolchainSpec>().unwrap();
+                let dist_data_path = dir.join("toolchain").join(spec.manifest_path());
                 let data = fs::read_to_string(dist_data_path.to_str().unwrap()).unwrap();
 
                 let dist_data = data.parse::<toml::Value>().unwrap();
-                let dist_dir_name = dist_data["date"].as_str().unwrap();
+                let resolved = match ResolvedToolchain::from_manifest(&spec, &dist_data) {
+                    Ok(resolved) => resolved,
+                    Err(err) => {
+                        error!("{}", err);
+                        process::exit(1);
+                    }
+                };
+                if !args.quiet {
+                    info!(
+                        "Resolved {} to rust {}",
+                        toolchain.channel, resolved.version
+                    );
+                }
+                write(
+                    dir.join("toolchain-info.toml"),
+                    toml::to_string(&resolved).unwrap(),
+                )
+                .unwrap();
+                artifact_channel = resolved.artifact_channel.clone();
+                let packages = match rust_pkg_gen::channel::resolve_components(
+                    &dist_data,
+                    toolchain.profile.as_deref(),
+                    &toolchain.components,
+                    &toolchain.platforms,
+                ) {
+                    Ok(packages) => packages,
+                    Err(err) => {
+                        error!("{}", err);
+                        process::exit(1);
+                    }
+                };
+                components = rust_pkg_gen::channel::install_components(
+                    &dist_data,
+                    &packages,
+                    &toolchain.platforms,
+                    &artifact_channel,
+                );
+                let dist_dir_name = resolved.date.as_str();
 
                 move_files_in_directory(
                     dir.join("toolchain")
@@ -295,13 +1108,13 @@ fn main() {
                         .unwrap();
                     fs::remove_file(
                         dir.join("toolchain")
-                            .join(format!("channel-rust-{}.toml", toolchain.channel)),
+                            .join(format!("channel-rust-{}.toml", spec.channel.as_str())),
                     )
                     .unwrap();
-                    fs::remove_file(
-                        dir.join("toolchain")
-                            .join(format!("channel-rust-{}.toml.sha256", toolchain.channel)),
-                    )
+                    fs::remove_file(dir.join("toolchain").join(format!(
+                        "channel-rust-{}.toml.sha256",
+                        spec.channel.as_str()
+                    )))
                     .unwrap();
                 }
             }
@@ -317,29 +1130,24 @@ fn main() {
 
                 let str_data = std::str::from_utf8(file.data.as_ref());
 
-                if str_data.is_ok() {
+                if let Ok(str_data) = str_data {
                     std::fs::write(
                         path,
                         str_data
-                            .unwrap()
-                            .replace("&?TOOLCHAIN.CHANNEL", &toolchain.channel)
-                            .replace("&?TOOLCHAIN.COMPONENTS", &toolchain.components.join(" "))
+                            .replace("&?TOOLCHAIN.CHANNEL", &artifact_channel)
+                            .replace(
+                                "&?TOOLCHAIN.COMPONENTS_CASES",
+                                &components_cases(&components),
+                            )
+                            .replace(
+                                "&?TOOLCHAIN.COMPONENTS_TABLE",
+                                &components_table(&components),
+                            )
                             .replace(
                                 "&?TOOLCHAIN.PKG",
-                                if toolchain
-                                    .format_map
-                                    .iter()
-                                    .map(|(_, v)| {
-                                        cfg.formats[v]
-                                            .iter()
-                                            .map(|v| v.format.clone())
-                                            .collect::<Vec<String>>()
-                                            .contains(&"pkg".to_string())
-                                    })
-                                    .collect::<Vec<bool>>()
-                                    .iter()
-                                    .any(|v| *v)
-                                {
+                                if toolchain.format_map.values().any(|v| {
+                                    cfg.formats[v].iter().any(|v| v.format == FormatKind::Pkg)
+                                }) {
                                     "$true"
                                 } else {
                                     "$false"
@@ -347,20 +1155,9 @@ fn main() {
                             )
                             .replace(
                                 "&?TOOLCHAIN.MSI",
-                                if toolchain
-                                    .format_map
-                                    .iter()
-                                    .map(|(_, v)| {
-                                        cfg.formats[v]
-                                            .iter()
-                                            .map(|v| v.format.clone())
-                                            .collect::<Vec<String>>()
-                                            .contains(&"msi".to_string())
-                                    })
-                                    .collect::<Vec<bool>>()
-                                    .iter()
-                                    .any(|v| *v)
-                                {
+                                if toolchain.format_map.values().any(|v| {
+                                    cfg.formats[v].iter().any(|v| v.format == FormatKind::Msi)
+                                }) {
                                     "$true"
                                 } else {
                                     "$false"
@@ -0,0 +1,342 @@
+//! Reading a package's config from the `Cargo.toml` of the project it's for.
+//!
+//! ```toml
+//! [package]
+//! name = "my-project"
+//!
+//! [package.metadata.rust-pkg-gen]
+//! toolchains = [{ channel = "stable", components = ["rustc", "cargo"], ... }]
+//! formats = { linux = ["gz-only"] }
+//! ```
+//!
+//! The `[package.metadata.rust-pkg-gen]`(or `[workspace.metadata.rust-pkg-gen]`)
+//! table has the same schema as a [`RustConfigInner`](crate::RustConfigInner),
+//! and becomes a package named after the project(or, for a virtual workspace,
+//! its directory). If it doesn't have `crates`, the crate set defaults to the
+//! project's own dependencies(every member's, for a workspace), under the
+//! package's name, and toolchains without a `crate-id`(or `crate-ids`) use it.
+//!
+//! That includes build- and dev-dependencies, since the project can't be
+//! built or tested offline without them. Dependencies on workspace members
+//! and on `path`s without a `version` are left out, since they aren't on a
+//! registry, and a `path` next to a `version` is dropped for the same reason.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use toml::{Table, Value};
+
+use crate::error::{ConfigError, KeySegment};
+
+/// The name of the table in `package.metadata` or `workspace.metadata`.
+pub const METADATA_KEY: &str = "rust-pkg-gen";
+
+/// The tables dependencies are read from, in order. See the [module
+/// documentation](self) for why all of them are.
+const DEPENDENCY_KEYS: [&str; 3] = ["dependencies", "build-dependencies", "dev-dependencies"];
+
+/// Whether `path` is a `Cargo.toml` rather than a `rust-config.toml`.
+pub fn is_manifest(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == "Cargo.toml")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Where the config read by [`from_manifest`] is in the `Cargo.toml`.
+pub struct Metadata {
+    /// The name of the package the config is read as.
+    pub package: String,
+    /// The key of the metadata table, like `package.metadata.rust-pkg-gen`.
+    pub key: Vec<KeySegment>,
+}
+
+impl Metadata {
+    /// Converts a key in the config into the key in the `Cargo.toml`.
+    pub fn file_key(&self, key: &[KeySegment]) -> Vec<KeySegment> {
+        match key.split_first() {
+            Some((KeySegment::Key(first), rest)) if *first == self.package => {
+                let mut out = self.key.clone();
+                out.extend(rest.iter().cloned());
+                out
+            }
+            _ => key.to_vec(),
+        }
+    }
+}
+
+/// Reads the config out of `manifest`, the parsed `contents` of the
+/// `Cargo.toml` at `path`. Returns a table with a single package, in the
+/// same format as a `rust-config.toml`.
+pub fn from_manifest(
+    manifest: &Table,
+    path: &Path,
+    contents: &str,
+) -> Result<(Table, Metadata), ConfigError> {
+    let dir = manifest_dir(path);
+    let (section, config) = ["package", "workspace"]
+        .into_iter()
+        .find_map(|section| {
+            let config = manifest.get(section)?.get("metadata")?.get(METADATA_KEY)?;
+            Some((section, config))
+        })
+        .ok_or_else(|| {
+            ConfigError::new(
+                path,
+                format!(
+                    "no [package.metadata.{0}] or [workspace.metadata.{0}] table",
+                    METADATA_KEY
+                ),
+            )
+        })?;
+    let key = vec![
+        KeySegment::Key(section.to_string()),
+        KeySegment::Key("metadata".to_string()),
+        KeySegment::Key(METADATA_KEY.to_string()),
+    ];
+    let Value::Table(config) = config else {
+        return Err(ConfigError::at(path, contents, &key, "expected a table"));
+    };
+    let mut config = config.clone();
+
+    let name = match manifest
+        .get("package")
+        .and_then(|package| package.get("name"))
+        .and_then(Value::as_str)
+    {
+        Some(name) if section == "package" => name.to_string(),
+        _ => dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| Some(dir.file_name()?.to_string_lossy().to_string()))
+            .unwrap_or_else(|| "workspace".to_string()),
+    };
+
+    if !config.contains_key("crates") {
+        let dependencies = if section == "package" {
+            let (root, root_dir) = workspace_root(manifest, dir);
+            let names = member_names(&members(&root, &root_dir.join("Cargo.toml"))?);
+            let mut dependencies = Table::new();
+            add_dependencies(&mut dependencies, manifest, &root, &names)
+                .map_err(|message| ConfigError::new(path, message))?;
+            dependencies
+        } else {
+            workspace_dependencies(manifest, path)?
+        };
+        let mut crates = Table::new();
+        crates.insert(name.clone(), Value::Table(dependencies));
+        config.insert("crates".to_string(), Value::Table(crates));
+        if let Some(Value::Array(toolchains)) = config.get_mut("toolchains") {
+            for toolchain in toolchains {
+                if let Value::Table(toolchain) = toolchain {
+                    if !toolchain.contains_key("crate-ids") {
+                        toolchain
+                            .entry("crate-id")
+                            .or_insert_with(|| Value::String(name.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut out = Table::new();
+    out.insert(name.clone(), Value::Table(config));
+    Ok((out, Metadata { package: name, key }))
+}
+
+/// The directory a `Cargo.toml` is in.
+fn manifest_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Finds the workspace the package in `manifest`(in `dir`) is in, by looking
+/// for a `Cargo.toml` with a `[workspace]` in `dir` and its parents. Falls
+/// back to the package itself.
+fn workspace_root(manifest: &Table, dir: &Path) -> (Table, PathBuf) {
+    if manifest.contains_key("workspace") {
+        return (manifest.clone(), dir.to_path_buf());
+    }
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    for parent in dir.ancestors().skip(1) {
+        let Ok(contents) = fs::read_to_string(parent.join("Cargo.toml")) else {
+            continue;
+        };
+        if let Ok(root) = toml::from_str::<Table>(&contents) {
+            if root.contains_key("workspace") {
+                return (root, parent.to_path_buf());
+            }
+        }
+    }
+    (manifest.clone(), dir)
+}
+
+/// Collects the dependencies of every member of the workspace in `root`, the
+/// parsed `Cargo.toml` at `path`. Dependencies on other members are skipped.
+fn workspace_dependencies(root: &Table, path: &Path) -> Result<Table, ConfigError> {
+    let members = members(root, path)?;
+    let names = member_names(&members);
+    let mut dependencies = Table::new();
+    for (member, manifest) in &members {
+        add_dependencies(&mut dependencies, manifest, root, &names)
+            .map_err(|message| ConfigError::new(member.join("Cargo.toml"), message))?;
+    }
+    Ok(dependencies)
+}
+
+/// Reads the members of the workspace in `root`, the parsed `Cargo.toml` at
+/// `path`, along with their directories. The root package(if there is one)
+/// is a member too.
+fn members(root: &Table, path: &Path) -> Result<Vec<(PathBuf, Table)>, ConfigError> {
+    let dir = manifest_dir(path);
+    let workspace = root.get("workspace");
+    let patterns = |key: &str| -> Vec<String> {
+        workspace
+            .and_then(|workspace| workspace.get(key))
+            .and_then(Value::as_array)
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let excluded: Vec<PathBuf> = patterns("exclude").iter().map(|p| dir.join(p)).collect();
+
+    let mut members: Vec<(PathBuf, Table)> = Vec::new();
+    if root.contains_key("package") {
+        members.push((dir.to_path_buf(), root.clone()));
+    }
+    for pattern in patterns("members") {
+        let full = dir.join(&pattern);
+        let paths = glob::glob(&full.to_string_lossy()).map_err(|err| {
+            ConfigError::new(path, format!("invalid member `{}`: {}", pattern, err))
+        })?;
+        for member in paths.filter_map(Result::ok) {
+            let manifest_path = member.join("Cargo.toml");
+            if excluded.contains(&member) || !manifest_path.exists() {
+                continue;
+            }
+            let member_contents = fs::read_to_string(&manifest_path).map_err(|err| {
+                ConfigError::new(&manifest_path, format!("failed to read file: {}", err))
+            })?;
+            let manifest: Table = toml::from_str(&member_contents)
+                .map_err(|err| ConfigError::from_toml(&manifest_path, &member_contents, err))?;
+            members.push((member, manifest));
+        }
+    }
+    Ok(members)
+}
+
+/// The package names of `members`.
+fn member_names(members: &[(PathBuf, Table)]) -> Vec<String> {
+    members
+        .iter()
+        .filter_map(|(_, manifest)| {
+            Some(manifest.get("package")?.get("name")?.as_str()?.to_string())
+        })
+        .collect()
+}
+
+/// Adds the dependencies of `manifest` to `out`, resolving `workspace = true`
+/// with the workspace in `root` and skipping the crates in `skip` and
+/// dependencies that are only a `path`. A crate that's already in `out` is
+/// kept, and target-specific dependencies keep their
+/// [`target`](crate::Crate::Detailed::target).
+fn add_dependencies(
+    out: &mut Table,
+    manifest: &Table,
+    root: &Table,
+    skip: &[String],
+) -> Result<(), String> {
+    // target-specific tables are paired with their target
+    let mut tables: Vec<(Option<&String>, &Table)> = DEPENDENCY_KEYS
+        .iter()
+        .filter_map(|key| Some((None, manifest.get(*key)?.as_table()?)))
+        .collect();
+    if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+        for (name, target) in targets {
+            tables.extend(
+                DEPENDENCY_KEYS
+                    .iter()
+                    .filter_map(|key| Some((Some(name), target.get(*key)?.as_table()?))),
+            );
+        }
+    }
+    let workspace_dependencies = root
+        .get("workspace")
+        .and_then(|workspace| workspace.get("dependencies"))
+        .and_then(Value::as_table);
+
+    let dependencies = tables
+        .into_iter()
+        .flat_map(|(target, table)| table.iter().map(move |entry| (target, entry)));
+    for (target, (name, dependency)) in dependencies {
+        let package = dependency
+            .get("package")
+            .and_then(Value::as_str)
+            .unwrap_or(name);
+        if out.contains_key(name) || skip.iter().any(|skip| skip == package) {
+            continue;
+        }
+        let dependency = match dependency {
+            Value::Table(table)
+                if table.get("workspace").and_then(Value::as_bool) == Some(true) =>
+            {
+                let inherited = workspace_dependencies
+                    .and_then(|dependencies| dependencies.get(name))
+                    .ok_or_else(|| format!("`{}` isn't in [workspace.dependencies]", name))?;
+                let mut inherited = match inherited {
+                    Value::Table(inherited) => inherited.clone(),
+                    version => {
+                        let mut table = Table::new();
+                        table.insert("version".to_string(), version.clone());
+                        table
+                    }
+                };
+                for (key, value) in table {
+                    match (key.as_str(), inherited.get_mut(key), value) {
+                        ("workspace", _, _) => {}
+                        ("features", Some(Value::Array(features)), Value::Array(more)) => {
+                            features.extend(more.iter().cloned())
+                        }
+                        _ => {
+                            inherited.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+                Value::Table(inherited)
+            }
+            dependency => dependency.clone(),
+        };
+        let dependency = match dependency {
+            Value::Table(mut table) if table.contains_key("path") => {
+                if !table.contains_key("version") {
+                    continue;
+                }
+                table.remove("path");
+                Value::Table(table)
+            }
+            dependency => dependency,
+        };
+        let dependency = match (target, dependency) {
+            (Some(target), Value::Table(mut table)) => {
+                table.insert("target".to_string(), Value::String(target.clone()));
+                Value::Table(table)
+            }
+            (Some(target), version) => {
+                let mut table = Table::new();
+                table.insert("version".to_string(), version);
+                table.insert("target".to_string(), Value::String(target.clone()));
+                Value::Table(table)
+            }
+            (None, dependency) => dependency,
+        };
+        out.insert(name.clone(), dependency);
+    }
+    Ok(())
+}
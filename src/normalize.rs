@@ -0,0 +1,367 @@
+//! Normalizes `rust-config.toml` files, which is what `rust-pkg-gen fmt` does.
+//!
+//! [`normalize`] checks the config with [`parse_str_with`] but normalizes
+//! the file as written, so [`defaults`](crate::inherit), `extends`,
+//! [`include`](crate::include), and `${VAR}`s are kept. [`expanded`](used by
+//! `fmt --expanded`, which only prints it) is [`to_toml_string`] of the parsed
+//! config instead, with all of them expanded into every package. Either way,
+//! packages and maps are sorted(with `[defaults]` first), the keys of
+//! packages, toolchains and crates are put in the same order as their fields,
+//! formats are kept as written(or always get a suffix, when expanded), and
+//! toolchains are written as arrays of tables. Since that loses every comment,
+//! the comments from the original file are then copied onto the matching keys,
+//! tables, and array elements wherever there's still somewhere for them to go.
+
+use std::path::Path;
+
+use serde::{ser::SerializeMap, ser::SerializeSeq, Serialize};
+use toml_edit::{Array, DocumentMut, Item, RawString, Table, TableLike, Value};
+
+use crate::{
+    error::ConfigError,
+    include::INCLUDE_KEY,
+    inherit::{DEFAULTS_KEY, EXTENDS_KEY, TOOLCHAIN_DEFAULTS_KEY},
+    metadata, parse_str, parse_str_with, to_toml_string, LoadOptions,
+};
+
+/// The order of the keys of a package, or of `[defaults]`.
+const PACKAGE_KEYS: &[&str] = &[
+    EXTENDS_KEY,
+    INCLUDE_KEY,
+    TOOLCHAIN_DEFAULTS_KEY,
+    "toolchains",
+    "crates",
+    "patches",
+    "formats",
+];
+/// The order of the keys of a [`Toolchain`](crate::Toolchain).
+const TOOLCHAIN_KEYS: &[&str] = &[
+    EXTENDS_KEY,
+    "edition",
+    "channel",
+    "profile",
+    "components",
+    "crate-id",
+    "crate-ids",
+    "exclude-crates",
+    "platforms",
+    "targets",
+    "format-map",
+];
+/// The order of the keys of a [`Crate::Detailed`](crate::Crate::Detailed).
+const CRATE_KEYS: &[&str] = &[
+    "version",
+    "versions",
+    "latest-n",
+    "features",
+    "default-features",
+    "optional",
+    "package",
+    "registry",
+    "path",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "target",
+];
+
+/// Normalizes the contents of a `rust-config.toml` file, keeping `[defaults]`,
+/// `extends`, `include`, and `${VAR}`s as written. `path` is only used for
+/// error reporting.
+pub fn normalize(contents: &str, path: &Path) -> Result<String, ConfigError> {
+    check_path(path)?;
+    parse_str_with(
+        contents,
+        path,
+        &LoadOptions {
+            allow_undefined: true,
+            ..LoadOptions::default()
+        },
+    )?;
+    let raw: toml::Table =
+        toml::from_str(contents).map_err(|err| ConfigError::from_toml(path, contents, err))?;
+    let normalized = toml::to_string_pretty(&Ordered::new(toml::Value::Table(raw), Context::Root))
+        .map_err(|err| ConfigError::new(path, err.to_string()))?;
+    Ok(copy_comments(contents, normalized))
+}
+
+/// The contents of a `rust-config.toml` file with inheritance, includes, and
+/// `${VAR}`s expanded into every package, normalized like [`normalize`].
+/// This isn't meant to replace the file, since it loses all of those.
+/// `path` is only used for error reporting.
+pub fn expanded(contents: &str, path: &Path) -> Result<String, ConfigError> {
+    check_path(path)?;
+    let config = parse_str(contents, path)?;
+    let normalized =
+        to_toml_string(&config).map_err(|err| ConfigError::new(path, err.to_string()))?;
+    Ok(copy_comments(contents, normalized))
+}
+
+fn check_path(path: &Path) -> Result<(), ConfigError> {
+    if metadata::is_manifest(path) {
+        return Err(ConfigError::new(
+            path,
+            "only rust-config.toml files can be normalized, not Cargo.toml",
+        ));
+    }
+    Ok(())
+}
+
+/// Copies the comments of `contents` onto `normalized`.
+fn copy_comments(contents: &str, normalized: String) -> String {
+    let (Ok(original), Ok(mut doc)) = (
+        contents.parse::<DocumentMut>(),
+        normalized.parse::<DocumentMut>(),
+    ) else {
+        return normalized;
+    };
+
+    copy_table_comments(original.as_table(), doc.as_table_mut());
+    if let Some(trailing) = comment_block(Some(original.trailing())) {
+        doc.set_trailing(format!("\n{}", trailing));
+    }
+    doc.to_string().trim_start().to_string()
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Where in a config a value is, which decides how it's normalized.
+enum Context {
+    Root,
+    Package,
+    Toolchain,
+    CrateIds,
+    Crates,
+    Crate,
+    Other,
+}
+
+impl Context {
+    fn child(self, key: &str) -> Context {
+        match (self, key) {
+            (Context::Root, _) => Context::Package,
+            (Context::Package, "toolchains" | TOOLCHAIN_DEFAULTS_KEY) => Context::Toolchain,
+            (Context::Package, "crates" | "patches") => Context::CrateIds,
+            (Context::CrateIds, _) => Context::Crates,
+            (Context::Crates, _) => Context::Crate,
+            _ => Context::Other,
+        }
+    }
+
+    /// The keys that go first, in order. Every other key is sorted.
+    fn key_order(self) -> &'static [&'static str] {
+        match self {
+            Context::Root => &[INCLUDE_KEY, DEFAULTS_KEY],
+            Context::Package => PACKAGE_KEYS,
+            Context::Toolchain => TOOLCHAIN_KEYS,
+            Context::Crate => CRATE_KEYS,
+            _ => &[],
+        }
+    }
+}
+
+/// A TOML value with the keys of its tables in the order they're written in.
+enum Ordered {
+    Table(Vec<(String, Ordered)>),
+    Array(Vec<Ordered>),
+    Value(toml::Value),
+}
+
+impl Ordered {
+    fn new(value: toml::Value, context: Context) -> Ordered {
+        match value {
+            toml::Value::Table(table) => {
+                let order = context.key_order();
+                let mut entries: Vec<(String, toml::Value)> = table.into_iter().collect();
+                entries.sort_by_key(|(key, _)| {
+                    (
+                        order.iter().position(|k| k == key).unwrap_or(order.len()),
+                        key.clone(),
+                    )
+                });
+                Ordered::Table(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| {
+                            let child = context.child(&key);
+                            (key, Ordered::new(value, child))
+                        })
+                        .collect(),
+                )
+            }
+            toml::Value::Array(array) => Ordered::Array(
+                array
+                    .into_iter()
+                    .map(|value| Ordered::new(value, context))
+                    .collect(),
+            ),
+            value => Ordered::Value(value),
+        }
+    }
+}
+
+impl Serialize for Ordered {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Ordered::Table(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Ordered::Array(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Ordered::Value(value) => value.serialize(serializer),
+        }
+    }
+}
+
+/// Returns the lines of `raw` as a block to be placed at the start of a line,
+/// if `raw` contains a comment. Blank lines are kept, indentation isn't.
+fn comment_block(raw: Option<&RawString>) -> Option<String> {
+    let raw = raw?.as_str()?;
+    if !raw.contains('#') {
+        return None;
+    }
+    let mut lines: Vec<&str> = raw.split('\n').map(str::trim).collect();
+    // the last "line" is the indentation before whatever `raw` is attached to
+    lines.pop();
+    while lines.first() == Some(&"") {
+        lines.remove(0);
+    }
+    Some(lines.iter().map(|line| format!("{}\n", line)).collect())
+}
+
+/// Whether `raw` contains a comment.
+fn has_comment(raw: Option<&RawString>) -> bool {
+    raw.and_then(RawString::as_str)
+        .is_some_and(|raw| raw.contains('#'))
+}
+
+fn copy_table_comments(original: &dyn TableLike, new: &mut Table) {
+    let keys: Vec<String> = new.iter().map(|(key, _)| key.to_string()).collect();
+    for key in keys {
+        let Some((original_key, original_item)) = original.get_key_value(&key) else {
+            continue;
+        };
+        if let Some(comment) = comment_block(original_key.leaf_decor().prefix()) {
+            if new.get(&key).is_some_and(Item::is_value) {
+                if let Some(mut new_key) = new.key_mut(&key) {
+                    new_key.leaf_decor_mut().set_prefix(comment);
+                }
+            } else if let Some(item) = new.get_mut(&key) {
+                set_header_comment(item, comment);
+            }
+        }
+        if let Some(item) = new.get_mut(&key) {
+            copy_item_comments(original_item, item);
+        }
+    }
+}
+
+fn copy_item_comments(original: &Item, new: &mut Item) {
+    match new {
+        Item::Table(table) => {
+            if let Some(original_table) = original.as_table_like() {
+                if let Some(comment) = original
+                    .as_table()
+                    .and_then(|original| comment_block(original.decor().prefix()))
+                {
+                    set_table_comment(table, comment);
+                }
+                copy_table_comments(original_table, table);
+            }
+        }
+        Item::ArrayOfTables(tables) => {
+            let originals: Vec<&dyn TableLike> = match original {
+                Item::ArrayOfTables(original) => {
+                    original.iter().map(|t| t as &dyn TableLike).collect()
+                }
+                Item::Value(Value::Array(original)) => original
+                    .iter()
+                    .filter_map(|v| v.as_inline_table().map(|t| t as &dyn TableLike))
+                    .collect(),
+                _ => return,
+            };
+            if originals.len() != tables.len() {
+                return;
+            }
+            if let Item::ArrayOfTables(original) = original {
+                for (original, table) in original.iter().zip(tables.iter_mut()) {
+                    if let Some(comment) = comment_block(original.decor().prefix()) {
+                        table.decor_mut().set_prefix(format!("\n{}", comment));
+                    }
+                }
+            }
+            for (original, table) in originals.into_iter().zip(tables.iter_mut()) {
+                copy_table_comments(original, table);
+            }
+        }
+        Item::Value(value) => {
+            if let Some(original) = original.as_value() {
+                if has_comment(original.decor().suffix()) {
+                    value
+                        .decor_mut()
+                        .set_suffix(original.decor().suffix().unwrap().clone());
+                }
+                if let (Some(original), Some(array)) = (original.as_array(), value.as_array_mut()) {
+                    copy_array_comments(original, array);
+                }
+            }
+        }
+        Item::None => {}
+    }
+}
+
+/// Copies the comments inside an array. Arrays aren't reordered by
+/// normalization, so this only needs the elements to line up.
+fn copy_array_comments(original: &Array, new: &mut Array) {
+    let commented = has_comment(Some(original.trailing()))
+        || original.iter().any(|value| {
+            has_comment(value.decor().prefix()) || has_comment(value.decor().suffix())
+        });
+    if !commented || original.len() != new.len() {
+        return;
+    }
+    for (original, value) in original.iter().zip(new.iter_mut()) {
+        *value.decor_mut() = original.decor().clone();
+    }
+    new.set_trailing(original.trailing().clone());
+    new.set_trailing_comma(original.trailing_comma());
+}
+
+/// Puts `comment` above the header of `item`, or above the first header
+/// inside it if it doesn't have one of its own.
+fn set_header_comment(item: &mut Item, comment: String) {
+    match item {
+        Item::Table(table) => set_table_comment(table, comment),
+        Item::ArrayOfTables(tables) => {
+            if let Some(table) = tables.get_mut(0) {
+                table.decor_mut().set_prefix(format!("\n{}", comment));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn set_table_comment(table: &mut Table, comment: String) {
+    if !table.is_implicit() {
+        table.decor_mut().set_prefix(format!("\n{}", comment));
+        return;
+    }
+    if let Some((_, item)) = table
+        .iter_mut()
+        .find(|(_, item)| item.is_table() || item.is_array_of_tables())
+    {
+        set_header_comment(item, comment);
+    }
+}
@@ -0,0 +1,255 @@
+//! `[patch]` overrides, for crates that have to come from a patched fork
+//! instead of crates.io.
+//!
+//! ```toml
+//! [pkg.crates.base]
+//! serde = "1"
+//!
+//! [pkg.patches.base]
+//! serde = { git = "https://github.com/example/serde", branch = "fix" }
+//! ```
+//!
+//! `patches` has the same shape as `crates`: each crate list ID maps to the
+//! crates patched for the toolchains using that list, which need a `path` or a
+//! `git`. They become the `[patch.crates-io]` table of the generated
+//! `Cargo.toml`(see [`manifest_table`]), and since the local registry only
+//! contains crates.io crates, [`vendor`] copies their sources into the
+//! package's crates directory, where the crates README tells users how to
+//! point their own `[patch]` at them(see [`readme`]). A relative `path` is
+//! relative to the file the patch is in, and is made absolute when the config
+//! is loaded(see [`absolute_paths`]).
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Error};
+
+use crate::Crate;
+
+/// The directory in the crates directory the patched sources are copied into.
+pub const PATCHES_DIR: &str = "patches";
+
+/// Makes the `path` of every patch in `patches`, the `patches` table of a
+/// config file before it's deserialized, absolute, relative to `dir`(the
+/// directory of that file). Otherwise it'd be relative to wherever the
+/// generated `Cargo.toml` ends up.
+pub fn absolute_paths(patches: &mut toml::Value, dir: &Path) {
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let Some(lists) = patches.as_table_mut() else {
+        return;
+    };
+    for (_, list) in lists.iter_mut() {
+        let Some(list) = list.as_table_mut() else {
+            continue;
+        };
+        for (_, krate) in list.iter_mut() {
+            let entries: Vec<&mut toml::Value> = match krate {
+                toml::Value::Array(entries) => entries.iter_mut().collect(),
+                krate => vec![krate],
+            };
+            for entry in entries {
+                if let Some(toml::Value::String(path)) = entry.get_mut("path") {
+                    let joined = dir.join(&*path);
+                    *path = joined
+                        .canonicalize()
+                        .unwrap_or(joined)
+                        .to_string_lossy()
+                        .to_string();
+                }
+            }
+        }
+    }
+}
+
+/// Every patch, [lowered](Crate::lower), sorted by name.
+fn lowered(patches: &HashMap<String, Crate>) -> Vec<(String, Crate)> {
+    patches
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .flat_map(|(name, krate)| krate.lower(name))
+        .collect()
+}
+
+/// Writes `patches` as the `[patch.crates-io]` table of a Cargo.toml. Returns
+/// an empty string if there aren't any.
+pub fn manifest_table(patches: &HashMap<String, Crate>) -> String {
+    if patches.is_empty() {
+        return String::new();
+    }
+    let mut crates_io = toml_edit::Table::new();
+    for (name, krate) in lowered(patches) {
+        crates_io.insert(&name, toml_edit::Item::Value(krate.to_toml()));
+    }
+    let mut patch = toml_edit::Table::new();
+    patch.set_implicit(true);
+    patch.insert("crates-io", toml_edit::Item::Table(crates_io));
+    let mut document = toml_edit::DocumentMut::new();
+    document.insert("patch", toml_edit::Item::Table(patch));
+    document.to_string()
+}
+
+/// Copies the source of every patch into `{crates_dir}/patches/{name}`,
+/// cloning the ones from git. Like cargo, the crate is looked for anywhere in
+/// a git repository(which is often a workspace with the crate in a
+/// subdirectory), and only its directory is copied.
+pub fn vendor(
+    patches: &HashMap<String, Crate>,
+    crates_dir: &Path,
+    quiet: bool,
+) -> Result<(), Error> {
+    for (name, krate) in lowered(patches) {
+        let dest = crates_dir.join(PATCHES_DIR).join(&name);
+        if dest.exists() {
+            fs::remove_dir_all(&dest)?;
+        }
+        match krate {
+            Crate::Detailed {
+                path: Some(path), ..
+            } => copy_dir(Path::new(&path), &dest)
+                .map_err(|err| anyhow!("failed to copy patch `{}` from {}: {}", name, path, err))?,
+            Crate::Detailed {
+                git: Some(git),
+                package,
+                branch,
+                tag,
+                rev,
+                ..
+            } => {
+                let repo = crates_dir.join(PATCHES_DIR).join(format!(".{}.git", name));
+                if repo.exists() {
+                    fs::remove_dir_all(&repo)?;
+                }
+                let mut clone = Command::new("git");
+                clone.arg("clone");
+                if let Some(reference) = branch.or(tag) {
+                    clone.args(["--branch", &reference]);
+                }
+                clone.arg(&git).arg(&repo);
+                run(clone, quiet).map_err(|err| {
+                    anyhow!("failed to clone patch `{}` from {}: {}", name, git, err)
+                })?;
+                if let Some(rev) = rev {
+                    let mut checkout = Command::new("git");
+                    checkout.arg("-C").arg(&repo).args(["checkout", &rev]);
+                    run(checkout, quiet).map_err(|err| {
+                        anyhow!(
+                            "failed to check out `{}` for patch `{}`: {}",
+                            rev,
+                            name,
+                            err
+                        )
+                    })?;
+                }
+                let package = package.unwrap_or_else(|| name.clone());
+                let source = find_package(&repo, &package)?.ok_or_else(|| {
+                    anyhow!(
+                        "patch `{}` from {} doesn't have a package named `{}`",
+                        name,
+                        git,
+                        package
+                    )
+                })?;
+                copy_dir(&source, &dest)?;
+                fs::remove_dir_all(&repo)?;
+            }
+            _ => return Err(anyhow!("patch `{}` needs a `path` or `git`", name)),
+        }
+    }
+    Ok(())
+}
+
+/// Runs `command`, failing if it doesn't succeed.
+fn run(mut command: Command, quiet: bool) -> Result<(), Error> {
+    if quiet {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    let status = command.status()?;
+    if !status.success() {
+        return Err(anyhow!("{}", status));
+    }
+    Ok(())
+}
+
+/// Finds the directory in `dir` with the Cargo.toml of the package `package`,
+/// closest to `dir` first. `target` and `.git` aren't searched.
+fn find_package(dir: &Path, package: &str) -> Result<Option<PathBuf>, Error> {
+    let mut queue = VecDeque::from([dir.to_path_buf()]);
+    while let Some(dir) = queue.pop_front() {
+        // a Cargo.toml that can't be parsed(like a test fixture) isn't the package's
+        let name = fs::read_to_string(dir.join("Cargo.toml"))
+            .ok()
+            .and_then(|manifest| toml::from_str::<toml::Table>(&manifest).ok())
+            .and_then(|manifest| {
+                manifest
+                    .get("package")?
+                    .get("name")?
+                    .as_str()
+                    .map(str::to_string)
+            });
+        if name.as_deref() == Some(package) {
+            return Ok(Some(dir));
+        }
+        let mut entries: Vec<_> = fs::read_dir(&dir)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            if entry.file_type()?.is_dir() && name != "target" && name != ".git" {
+                queue.push_back(entry.path());
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Copies the directory `from` to `to`, other than `target` and `.git`.
+fn copy_dir(from: &Path, to: &Path) -> Result<(), Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == "target" || name == ".git" {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to.join(&name))?;
+        } else {
+            fs::copy(entry.path(), to.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+/// The section of the crates README explaining how to use the vendored
+/// patches. `{?TOOLCHAIN.CRATES_DIR}` is left in for the path of the crates
+/// directory. Returns an empty string if there aren't any patches.
+pub fn readme(patches: &HashMap<String, Crate>) -> String {
+    if patches.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from(
+        "\n## Patched crates\n\nSome crates are patched, and their sources are in the `patches` folder instead of the registry. To use them, also add this to your `.cargo/config[.toml]`:\n\n```toml\n[patch.crates-io]\n",
+    );
+    for (name, krate) in lowered(patches) {
+        let package = match krate {
+            Crate::Detailed {
+                package: Some(package),
+                ..
+            } if package != name => format!(", package = \"{}\"", package),
+            _ => String::new(),
+        };
+        out += &format!(
+            "{} = {{ path = '{{?TOOLCHAIN.CRATES_DIR}}/{}/{}'{} }}\n",
+            name, PATCHES_DIR, name, package
+        );
+    }
+    out += "```\n";
+    out
+}
@@ -0,0 +1,260 @@
+//! A JSON Schema for `rust-config.toml`, so editors(like ones using Taplo or
+//! Even Better TOML) can validate and autocomplete configs. Printed by
+//! `rust-pkg-gen schema`.
+//!
+//! Most of it is generated from [`RustConfigInner`], [`Toolchain`], and
+//! [`Crate`], with formats, channels, and targets restricted to the values
+//! [`validate`](crate::validate) accepts. On top of that, [`schema`] adds the
+//! keys that only exist in the file([`include`](crate::include),
+//! [`defaults`, and `extends`](crate::inherit)). Since inheritance can fill in
+//! any key, no key is required.
+
+use regex::Regex;
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    schema::{
+        ArrayValidation, InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject,
+        SubschemaValidation,
+    },
+    JsonSchema,
+};
+use serde_json::Value;
+
+use crate::{
+    channel::PROFILES,
+    include::INCLUDE_KEY,
+    inherit::{DEFAULTS_KEY, EXTENDS_KEY, TOOLCHAIN_DEFAULTS_KEY},
+    targets, Format, FormatKind, RustConfigInner, Suffix, Toolchain,
+};
+
+/// A schema with a description.
+fn described(mut schema: SchemaObject, description: &str) -> Schema {
+    schema.metadata().description = Some(description.to_string());
+    schema.into()
+}
+
+/// A schema of a single type.
+fn of_type(instance_type: InstanceType) -> SchemaObject {
+    SchemaObject {
+        instance_type: Some(instance_type.into()),
+        ..SchemaObject::default()
+    }
+}
+
+/// A string schema that only allows `values`.
+fn one_of_strings<'a>(values: impl IntoIterator<Item = &'a str>) -> SchemaObject {
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        enum_values: Some(values.into_iter().map(Value::from).collect()),
+        ..SchemaObject::default()
+    }
+}
+
+/// A string schema that has to match `pattern`.
+fn pattern(pattern: &str) -> SchemaObject {
+    let mut schema = of_type(InstanceType::String);
+    schema.string().pattern = Some(pattern.to_string());
+    schema
+}
+
+/// An array of `items`.
+fn array_of(items: Schema) -> SchemaObject {
+    SchemaObject {
+        instance_type: Some(InstanceType::Array.into()),
+        array: Some(Box::new(ArrayValidation {
+            items: Some(items.into()),
+            ..ArrayValidation::default()
+        })),
+        ..SchemaObject::default()
+    }
+}
+
+/// The schema of [`Toolchain::channel`]: a release channel, a version, or a
+/// dated channel. See [`ToolchainSpec`](crate::channel::ToolchainSpec).
+pub fn channel(_: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![
+                one_of_strings(targets::RELEASE_CHANNELS).into(),
+                described(
+                    pattern(r"^\d+\.\d+(\.\d+)?$"),
+                    "A version, like `1.83.0` or `1.82`.",
+                ),
+                described(
+                    pattern(r"^(stable|beta|nightly)-\d{4}-\d{2}-\d{2}$"),
+                    "A dated channel, like `nightly-2024-11-30`.",
+                ),
+            ]),
+            ..SubschemaValidation::default()
+        })),
+        ..SchemaObject::default()
+    }
+    .into()
+}
+
+/// The schema of [`Toolchain::profile`]: one of the
+/// [profiles](crate::channel::PROFILES).
+pub fn profile(_: &mut SchemaGenerator) -> Schema {
+    one_of_strings(PROFILES).into()
+}
+
+/// What a target triple looks like, for targets that aren't in
+/// [`TARGETS`](targets::TARGETS) yet.
+pub const TRIPLE_PATTERN: &str = r"^[a-z0-9_.]+(-[a-z0-9_.]+){1,3}$";
+
+/// The schema of [`Toolchain::platforms`] and [`Toolchain::targets`]: an array
+/// of [`host`](targets::HOST), [aliases](targets::ALIASES), globs, and target
+/// triples. Since a channel can have targets newer than
+/// [`TARGETS`](targets::TARGETS), any triple is allowed, and the known ones
+/// are only there for editors to suggest.
+pub fn targets(_: &mut SchemaGenerator) -> Schema {
+    let known = targets::TARGETS
+        .into_iter()
+        .chain([targets::HOST])
+        .chain(targets::ALIASES.map(|(alias, _)| alias));
+    let target = SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![
+                one_of_strings(known).into(),
+                described(
+                    pattern(TRIPLE_PATTERN),
+                    "A target triple, like `x86_64-unknown-linux-gnu`.",
+                ),
+                described(
+                    pattern(r"[*?\[]"),
+                    "A glob matching targets, like `*-unknown-linux-musl`.",
+                ),
+            ]),
+            ..SubschemaValidation::default()
+        })),
+        ..SchemaObject::default()
+    };
+    array_of(target.into()).into()
+}
+
+impl JsonSchema for Format {
+    fn schema_name() -> String {
+        "Format".to_string()
+    }
+
+    /// Every valid format, with and without a suffix.
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let mut formats = Vec::new();
+        for kind in FormatKind::ALL {
+            formats.push(kind.to_string());
+            for suffix in [Suffix::IfAvailable, Suffix::Only] {
+                formats.push(format!("{}-{}", kind, suffix));
+            }
+        }
+        described(
+            one_of_strings(formats.iter().map(String::as_str)),
+            "A format, optionally with a suffix(`-if-available`, the default, or `-only`).",
+        )
+    }
+}
+
+/// Makes every key of the object schema `name` in `gen` optional, and adds
+/// `extra` properties to it.
+fn loosen(gen: &mut SchemaGenerator, name: &str, extra: Vec<(&str, Schema)>) {
+    let Some(Schema::Object(schema)) = gen.definitions_mut().get_mut(name) else {
+        return;
+    };
+    let object = schema.object();
+    object.required.clear();
+    for (key, property) in extra {
+        object.properties.insert(key.to_string(), property);
+    }
+}
+
+/// Generates the schema of a whole `rust-config.toml`.
+pub fn schema() -> RootSchema {
+    let mut settings = SchemaSettings::draft07();
+    // TOML doesn't have null, so optional keys are just left out
+    settings.option_add_null_type = false;
+    let mut gen = settings.into_generator();
+    let package = gen.subschema_for::<RustConfigInner>();
+    let toolchain = gen.subschema_for::<Toolchain>();
+
+    let include = || {
+        described(
+            array_of(of_type(InstanceType::String).into()),
+            "Other files(or globs) to read crate, patch, and format lists from.",
+        )
+    };
+    let extends = |description: &str| described(of_type(InstanceType::String), description);
+    loosen(
+        &mut gen,
+        &Toolchain::schema_name(),
+        vec![(
+            EXTENDS_KEY,
+            extends("A toolchain to inherit from, like `other` or `other[1]`."),
+        )],
+    );
+    loosen(
+        &mut gen,
+        &RustConfigInner::schema_name(),
+        vec![
+            (EXTENDS_KEY, extends("A package to inherit from.")),
+            (INCLUDE_KEY, include()),
+        ],
+    );
+
+    let mut defaults = match gen.definitions().get(&RustConfigInner::schema_name()) {
+        Some(Schema::Object(schema)) => schema.clone(),
+        _ => SchemaObject::default(),
+    };
+    let properties = &mut defaults.object().properties;
+    properties.remove(EXTENDS_KEY);
+    properties.insert(TOOLCHAIN_DEFAULTS_KEY.to_string(), toolchain);
+    defaults.metadata().description =
+        Some("Defaults for every package, and(in `toolchain`) every toolchain.".to_string());
+
+    let mut root = of_type(InstanceType::Object);
+    root.metadata().title = Some("rust-config.toml".to_string());
+    *root.object() = ObjectValidation {
+        properties: [
+            (INCLUDE_KEY.to_string(), include()),
+            (DEFAULTS_KEY.to_string(), defaults.into()),
+        ]
+        .into_iter()
+        .collect(),
+        additional_properties: Some(Box::new(package)),
+        ..ObjectValidation::default()
+    };
+    let schema = RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        schema: root,
+        definitions: gen.take_definitions(),
+    };
+    let mut value = serde_json::to_value(schema).unwrap();
+    // `[text](link)` and `[`Item`]`
+    let links = Regex::new(r"\[([^\]]+)\]\([^)]*\)|\[(`[^`\]]+`)\]").unwrap();
+    strip_links(&mut value, &links);
+    serde_json::from_value(value).unwrap()
+}
+
+/// Replaces the rustdoc links in every description in `value`(which come
+/// from doc comments) with just their text, since editors show them as is.
+fn strip_links(value: &mut Value, links: &Regex) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::String(description) if key == "description" => {
+                        *description = links.replace_all(description, "$1$2").to_string();
+                    }
+                    value => strip_links(value, links),
+                }
+            }
+        }
+        Value::Array(array) => array.iter_mut().for_each(|value| strip_links(value, links)),
+        _ => {}
+    }
+}
+
+/// [`schema`], as pretty-printed JSON.
+pub fn to_json_string() -> String {
+    let mut out = serde_json::to_string_pretty(&schema()).unwrap();
+    out.push('\n');
+    out
+}
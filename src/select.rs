@@ -0,0 +1,164 @@
+//! Building only some of the packages and toolchains in a config, with
+//! `--package` and `--toolchain`, and listing them with `rust-pkg-gen list`.
+//!
+//! A package filter is a package name, which can be a glob(`web-*`). A
+//! toolchain filter is either the index of a toolchain in its package's
+//! `toolchains`(`0`) or its channel(`nightly`). Each kind of filter can be
+//! given more than once, and anything matching any of them is selected. With
+//! no filters of a kind, everything is selected.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Error};
+
+use crate::{targets, RustConfig, RustConfigInner, Toolchain};
+
+#[derive(Debug, Clone, Default)]
+/// Which packages and toolchains to select. See the [module docs](self).
+pub struct Selection {
+    /// Package names or globs.
+    pub packages: Vec<String>,
+    /// Toolchain indices or channels.
+    pub toolchains: Vec<String>,
+}
+
+impl Selection {
+    /// Whether the toolchain at `idx` matches `filter`.
+    fn toolchain_matches(filter: &str, idx: usize, toolchain: &Toolchain) -> bool {
+        match filter.parse::<usize>() {
+            Ok(filter_idx) => filter_idx == idx,
+            Err(_) => filter == toolchain.channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A package picked by [`select`].
+pub struct Selected {
+    /// The name of the package.
+    pub name: String,
+    /// The package, with all of its toolchains.
+    pub package: RustConfigInner,
+    /// The indices of the selected toolchains in
+    /// [`package.toolchains`](RustConfigInner::toolchains).
+    pub toolchains: Vec<usize>,
+}
+
+impl Selected {
+    /// The selected toolchains.
+    pub fn toolchains(&self) -> impl Iterator<Item = &Toolchain> {
+        self.toolchains
+            .iter()
+            .map(|&idx| &self.package.toolchains[idx])
+    }
+}
+
+/// The packages in `config` matching `selection`, sorted by name. Packages
+/// without any matching toolchains are skipped.
+///
+/// Fails if a filter is an invalid glob or doesn't match anything, since
+/// that's almost always a typo.
+pub fn select(config: RustConfig, selection: &Selection) -> Result<Vec<Selected>, Error> {
+    let patterns = selection
+        .packages
+        .iter()
+        .map(|package| {
+            glob::Pattern::new(package)
+                .map_err(|err| anyhow!("invalid package pattern `{}`: {}", package, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for (filter, pattern) in selection.packages.iter().zip(&patterns) {
+        if !config.keys().any(|name| pattern.matches(name)) {
+            return Err(anyhow!("no package matches `{}`", filter));
+        }
+    }
+
+    let selected: Vec<Selected> = config
+        .into_iter()
+        .filter(|(name, _)| patterns.is_empty() || patterns.iter().any(|p| p.matches(name)))
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(name, package)| {
+            let toolchains = (0..package.toolchains.len())
+                .filter(|&idx| {
+                    selection.toolchains.is_empty()
+                        || selection.toolchains.iter().any(|filter| {
+                            Selection::toolchain_matches(filter, idx, &package.toolchains[idx])
+                        })
+                })
+                .collect();
+            Selected {
+                name,
+                package,
+                toolchains,
+            }
+        })
+        .collect();
+    for filter in &selection.toolchains {
+        let matched = selected.iter().any(|selected| {
+            selected.toolchains.iter().any(|&idx| {
+                Selection::toolchain_matches(filter, idx, &selected.package.toolchains[idx])
+            })
+        });
+        if !matched {
+            return Err(anyhow!("no selected toolchain matches `{}`", filter));
+        }
+    }
+    Ok(selected
+        .into_iter()
+        .filter(|selected| !selected.toolchains.is_empty())
+        .collect())
+}
+
+/// Describes `packages`, as returned by [`select`], for `rust-pkg-gen list`:
+/// each package, followed by its selected toolchains with their index, channel,
+/// crate lists, platforms, and targets. Aliases and globs are shown with what
+/// they [expand](targets::expand) to out of every known target, since the
+/// manifest isn't downloaded.
+pub fn list(packages: &[Selected]) -> String {
+    let mut out = String::new();
+    for selected in packages {
+        out += &format!("{}\n", selected.name);
+        for (&idx, toolchain) in selected.toolchains.iter().zip(selected.toolchains()) {
+            out += &format!(
+                "  [{}] {} (crates: {}; platforms: {}; targets: {})\n",
+                idx,
+                toolchain.channel,
+                list_or_none(&toolchain.all_crate_ids()),
+                list_or_none(&describe_targets(&toolchain.platforms)),
+                list_or_none(&describe_targets(&toolchain.targets)),
+            );
+        }
+    }
+    out
+}
+
+/// Each of `targets`, followed by what it expands to if it's a
+/// [pattern](targets::is_pattern).
+fn describe_targets(targets: &[String]) -> Vec<String> {
+    targets
+        .iter()
+        .map(|target| {
+            if !targets::is_pattern(target) {
+                return target.clone();
+            }
+            match targets::expand(std::slice::from_ref(target), &targets::TARGETS) {
+                Ok(expanded) => format!("{} = [{}]", target, expanded.join(", ")),
+                Err(err) => format!("{} = [{}]", target, err),
+            }
+        })
+        .collect()
+}
+
+/// Joins `items` with commas, or `none` if there aren't any.
+fn list_or_none(items: &[impl AsRef<str>]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
@@ -1,15 +1,19 @@
 //! Contains all valid targets and channels. Copied and modified from [rustup-mirror](https://crates.io/crates/rustup-mirror/0.8.1).
 
-/// Valid rust channels. Currently only stable, beta, and nightly.
+use std::{fs, io::Read, process::Command, sync::OnceLock};
+
+/// Valid named rust channels. Currently only stable, beta, and nightly.
+/// Versions and dated channels are also accepted; see [`crate::channel::ToolchainSpec`].
 pub const RELEASE_CHANNELS: [&str; 3] = ["stable", "beta", "nightly"];
 
 /// List of valid rust targets. According to rustup-mirror, can be generated with:
 /// ```bash
 /// rustc --print target-list | awk '{print "    \"" $1 "\","}'
 /// ```
-/// Ensure to rerun on nightly regularly!
-/// (if anyone has any better ideas that doesn't require remembering to run a
-/// command manually, please submit an issue or PR)
+/// Only used when the channel manifest isn't available(like in `validate`,
+/// which doesn't access the network); builds check targets against the
+/// manifest of their channel instead, so this being out of date only matters
+/// offline. Still, rerun on nightly every so often.
 pub const TARGETS: [&str; 271] = [
     "aarch64-apple-darwin",
     "aarch64-apple-ios",
@@ -283,3 +287,310 @@ pub const TARGETS: [&str; 271] = [
     "xtensa-esp32s3-espidf",
     "xtensa-esp32s3-none-elf",
 ];
+
+/// The [tier 1](https://doc.rust-lang.org/rustc/platform-support.html)
+/// targets. Written as `tier1` in `platforms` and `targets`. Like [`TARGETS`],
+/// needs to be updated by hand; last checked against Rust 1.90(October 2026),
+/// after `i686-pc-windows-gnu`(1.88) and `x86_64-apple-darwin`(1.90) moved to
+/// [`TIER_2_HOST`].
+pub const TIER_1: [&str; 7] = [
+    "aarch64-apple-darwin",
+    "aarch64-unknown-linux-gnu",
+    "i686-pc-windows-msvc",
+    "i686-unknown-linux-gnu",
+    "x86_64-pc-windows-gnu",
+    "x86_64-pc-windows-msvc",
+    "x86_64-unknown-linux-gnu",
+];
+
+/// The [tier 2 targets with host tools](https://doc.rust-lang.org/rustc/platform-support.html).
+/// Written as `tier2-host` in `platforms` and `targets`. Like [`TARGETS`],
+/// needs to be updated by hand.
+pub const TIER_2_HOST: [&str; 18] = [
+    "aarch64-pc-windows-msvc",
+    "aarch64-unknown-linux-musl",
+    "arm-unknown-linux-gnueabi",
+    "arm-unknown-linux-gnueabihf",
+    "armv7-unknown-linux-gnueabihf",
+    "i686-pc-windows-gnu",
+    "loongarch64-unknown-linux-gnu",
+    "loongarch64-unknown-linux-musl",
+    "powerpc-unknown-linux-gnu",
+    "powerpc64-unknown-linux-gnu",
+    "powerpc64le-unknown-linux-gnu",
+    "riscv64gc-unknown-linux-gnu",
+    "s390x-unknown-linux-gnu",
+    "x86_64-apple-darwin",
+    "x86_64-unknown-freebsd",
+    "x86_64-unknown-illumos",
+    "x86_64-unknown-linux-musl",
+    "x86_64-unknown-netbsd",
+];
+
+/// Names that can be used in `platforms` and `targets` instead of a list of
+/// targets.
+pub const ALIASES: [(&str, &[&str]); 2] = [("tier1", &TIER_1), ("tier2-host", &TIER_2_HOST)];
+
+/// The targets the alias `name` stands for, if it's an [alias](ALIASES).
+pub fn alias(name: &str) -> Option<&'static [&'static str]> {
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map(|(_, targets)| *targets)
+}
+
+/// Whether `target` is a glob(like `*-unknown-linux-musl`) rather than a
+/// single target.
+pub fn is_glob(target: &str) -> bool {
+    target.contains(['*', '?', '['])
+}
+
+/// Written in `platforms` and `targets` for the [host platform](host_triple),
+/// which is also the default `platforms`.
+pub const HOST: &str = "host";
+
+/// Whether `target` has to be [expanded](expand): it's [`HOST`], an
+/// [alias](ALIASES), or a [glob](is_glob).
+pub fn is_pattern(target: &str) -> bool {
+    target == HOST || alias(target).is_some() || is_glob(target)
+}
+
+/// Whether `target` is `pattern`, or is matched by it if it's a
+/// [pattern](is_pattern).
+pub fn matches(pattern: &str, target: &str) -> bool {
+    if pattern == HOST {
+        host_triple().is_some_and(|host| host == target)
+    } else if let Some(targets) = alias(pattern) {
+        targets.contains(&target)
+    } else if is_glob(pattern) {
+        glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(target))
+    } else {
+        pattern == target
+    }
+}
+
+/// Expands the [host, aliases, and globs](is_pattern) in `targets` into the targets
+/// in `available` they match, keeping everything else as is. The result is in
+/// the order of `targets`(and then `available`), without duplicates.
+///
+/// `available` is usually the targets `rust-std` is available for in a
+/// channel manifest(see [`available_targets`](crate::channel::available_targets)),
+/// or [`TARGETS`] if the manifest isn't known yet. Fails if a glob is invalid
+/// or a pattern doesn't match anything in `available`.
+pub fn expand(targets: &[String], available: &[impl AsRef<str>]) -> Result<Vec<String>, String> {
+    let mut expanded: Vec<String> = Vec::new();
+    for target in targets {
+        let matched: Vec<&str> = if is_pattern(target) {
+            if is_glob(target) {
+                glob::Pattern::new(target)
+                    .map_err(|err| format!("invalid target pattern `{}`: {}", target, err))?;
+            }
+            let matched: Vec<&str> = available
+                .iter()
+                .map(AsRef::as_ref)
+                .filter(|available| matches(target, available))
+                .collect();
+            if matched.is_empty() {
+                return Err(match host_triple() {
+                    _ if target != HOST => {
+                        format!("target pattern `{}` doesn't match any target", target)
+                    }
+                    Some(host) => format!("the host platform `{}` isn't available", host),
+                    None => "couldn't detect the host platform".to_string(),
+                });
+            }
+            matched
+        } else {
+            vec![target.as_str()]
+        };
+        for target in matched {
+            if !expanded.iter().any(|expanded| expanded == target) {
+                expanded.push(target.to_string());
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+/// The target triple of the machine `rust-pkg-gen` is running on. Returns
+/// `None` if it can't be detected. It isn't checked against [`TARGETS`], so
+/// a host that's missing from it is reported as unavailable when `host` is
+/// [expanded](expand) against a channel instead of as undetectable.
+///
+/// It's detected the same way `install.sh` does(see [`detect_host`]), so a
+/// package built for `host` installs on an identical machine. If that fails,
+/// like on Windows, the target `rust-pkg-gen` was built for is used instead.
+/// The result is cached.
+pub fn host_triple() -> Option<String> {
+    static HOST: OnceLock<Option<String>> = OnceLock::new();
+    HOST.get_or_init(|| detect_host().or_else(built_for))
+        .clone()
+}
+
+/// Runs `program` and returns its trimmed stdout, if it succeeds.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Detects the host platform like `get_architecture` in `install.sh`: with
+/// `uname`, `ldd --version`(for musl), `sysctl`(to see through Rosetta), the
+/// bitness of `/bin/sh`(for 32-bit userlands), and
+/// `/proc/cpuinfo`(for armv7 without NEON). See [`triple_from_uname`].
+pub fn detect_host() -> Option<String> {
+    let mut os = output("uname", &["-s"])?;
+    let mut cpu = output("uname", &["-m"])?;
+    let mut android = false;
+    let mut musl = false;
+    let mut userland_32 = false;
+    if os == "Linux" {
+        android = output("uname", &["-o"]).as_deref() == Some("Android");
+        // musl's ldd prints its version to stderr and exits with an error
+        musl = Command::new("ldd")
+            .arg("--version")
+            .output()
+            .is_ok_and(|out| {
+                String::from_utf8_lossy(&out.stdout).contains("musl")
+                    || String::from_utf8_lossy(&out.stderr).contains("musl")
+            });
+        // like `install.sh`, which checks the shell running it: ELF files start
+        // with "\x7fELF" and then 1 for 32-bit or 2 for 64-bit
+        let mut header = [0; 5];
+        userland_32 = fs::File::open("/bin/sh")
+            .and_then(|mut sh| sh.read_exact(&mut header))
+            .is_ok_and(|()| &header == b"\x7fELF\x01");
+    }
+    if os == "Darwin" {
+        let sysctl = |name: &str| output("sysctl", &[name]).is_some_and(|out| out.ends_with(": 1"));
+        if cpu == "i386" && sysctl("hw.optional.x86_64") {
+            cpu = "x86_64".to_string();
+        } else if cpu == "x86_64" && sysctl("hw.optional.arm64") {
+            cpu = "arm64".to_string();
+        }
+    }
+    if os == "SunOS" {
+        if output("/usr/bin/uname", &["-o"]).as_deref() == Some("illumos") {
+            os = "illumos".to_string();
+        }
+        if cpu == "i86pc" {
+            cpu = output("isainfo", &["-n"])?;
+        }
+    }
+    let triple = triple_from_uname(&os, &cpu, android, musl, userland_32)?;
+    if triple == "armv7-unknown-linux-gnueabihf" {
+        let has_neon = fs::read_to_string("/proc/cpuinfo").is_ok_and(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .filter(|line| line.starts_with("Features"))
+                .all(|line| line.contains("neon") || line.contains("simd"))
+        });
+        if !has_neon {
+            return Some("arm-unknown-linux-gnueabihf".to_string());
+        }
+    }
+    Some(triple)
+}
+
+/// Turns the output of `uname -s` and `uname -m` into a target triple, like
+/// `get_architecture` in `install.sh`. `android` is whether `uname -o` is
+/// `Android`, `musl` whether the libc is musl, and `userland_32` whether a
+/// 64-bit Linux kernel is running a 32-bit userland. Returns `None` for an
+/// unknown OS or CPU.
+pub fn triple_from_uname(
+    os: &str,
+    cpu: &str,
+    android: bool,
+    musl: bool,
+    userland_32: bool,
+) -> Option<String> {
+    let mut os = match os {
+        "Linux" if android => "linux-android",
+        "Linux" if musl => "unknown-linux-musl",
+        "Linux" => "unknown-linux-gnu",
+        "Android" => "linux-android",
+        "FreeBSD" => "unknown-freebsd",
+        "NetBSD" => "unknown-netbsd",
+        "DragonFly" => "unknown-dragonfly",
+        "Darwin" => "apple-darwin",
+        "illumos" => "unknown-illumos",
+        os if ["MINGW", "MSYS", "CYGWIN", "Windows_NT"]
+            .iter()
+            .any(|prefix| os.starts_with(prefix)) =>
+        {
+            "pc-windows-gnu"
+        }
+        _ => return None,
+    }
+    .to_string();
+    let eabihf = |os: &mut String| {
+        if os == "linux-android" {
+            *os = "linux-androideabi".to_string();
+        } else {
+            os.push_str("eabihf");
+        }
+    };
+    let mut cpu = match cpu {
+        "i386" | "i486" | "i686" | "i786" | "x86" => "i686",
+        "xscale" | "arm" => {
+            if os == "linux-android" {
+                os = "linux-androideabi".to_string();
+            }
+            "arm"
+        }
+        "armv6l" => {
+            eabihf(&mut os);
+            "arm"
+        }
+        "armv7l" | "armv8l" => {
+            eabihf(&mut os);
+            "armv7"
+        }
+        "aarch64" | "arm64" => "aarch64",
+        "x86_64" | "x86-64" | "x64" | "amd64" => "x86_64",
+        "ppc" => "powerpc",
+        "ppc64" => "powerpc64",
+        "ppc64le" => "powerpc64le",
+        "s390x" => "s390x",
+        "riscv64" => "riscv64gc",
+        "loongarch64" => "loongarch64",
+        _ => return None,
+    };
+    if os == "unknown-linux-gnu" && userland_32 {
+        cpu = match cpu {
+            "x86_64" => "i686",
+            "powerpc64" => "powerpc",
+            "aarch64" => {
+                eabihf(&mut os);
+                "armv7"
+            }
+            "riscv64gc" => return None,
+            cpu => cpu,
+        };
+    }
+    Some(format!("{}-{}", cpu, os))
+}
+
+/// The target triple `rust-pkg-gen` was built for. See [`host_triple`].
+fn built_for() -> Option<String> {
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i686",
+        "powerpc64" if cfg!(target_endian = "little") => "powerpc64le",
+        arch => arch,
+    };
+    let os = match std::env::consts::OS {
+        "linux" if cfg!(target_env = "musl") => "unknown-linux-musl",
+        "linux" => "unknown-linux-gnu",
+        "android" => "linux-android",
+        "macos" => "apple-darwin",
+        "windows" if cfg!(target_env = "msvc") => "pc-windows-msvc",
+        "windows" => "pc-windows-gnu",
+        "freebsd" => "unknown-freebsd",
+        "netbsd" => "unknown-netbsd",
+        "illumos" => "unknown-illumos",
+        _ => return None,
+    };
+    Some(format!("{}-{}", arch, os))
+}
@@ -4,9 +4,15 @@ cd "$(dirname "$0")" || exit
 cargo generate-lockfile --verbose
 "$CARGO_BIN_FILE_CARGO_LOCAL_REGISTRY" local-registry --sync Cargo.lock crates >/dev/null 2>&1
 # ^^^ is super jank, but it expects that the first argument is local-registry and acts strangely otherwise
+for manifest in versions/*/Cargo.toml; do
+    [[ -e "$manifest" ]] || continue
+    cargo generate-lockfile --verbose --manifest-path "$manifest"
+    "$CARGO_BIN_FILE_CARGO_LOCAL_REGISTRY" local-registry --no-delete --sync "$(dirname "$manifest")/Cargo.lock" crates >/dev/null 2>&1
+done
 
 mv crates/* .
 if [[ "$1" != "save" ]]; then
     rm Cargo.lock Cargo.toml rust-toolchain.toml src/main.rs .cargo/config.toml build.sh
     rm -d src .cargo crates
+    rm -rf versions
 fi
@@ -4,22 +4,30 @@ use proptest::prelude::*;
 #[test]
 fn format_from_string() {
     // this covers all valid cases but not invalid cases
-    for ele in FORMATS {
-        assert!(Format::from_str(ele).is_ok());
+    for kind in FormatKind::ALL {
+        let ele = kind.as_str();
+        assert!(ele.parse::<Format>().is_ok());
         assert!(Format::from_string(ele.to_string() + "-if-available").is_ok());
         assert!(Format::from_string(ele.to_string() + "-only").is_ok());
 
         assert_eq!(
             Format::from_str_no_err(ele),
             Format {
+                format: kind.clone(),
+                suffix: Suffix::IfAvailable
+            }
+        );
+        assert_eq!(
+            Format::parse_lenient(ele),
+            RawFormat {
                 format: ele.to_string(),
                 suffix: Suffix::IfAvailable
             }
         );
         assert_eq!(
-            Format::from_str(ele).unwrap(),
+            ele.parse::<Format>().unwrap(),
             Format {
-                format: ele.to_string(),
+                format: kind.clone(),
                 suffix: Suffix::IfAvailable
             }
         );
@@ -27,14 +35,14 @@ fn format_from_string() {
         assert_eq!(
             Format::from_string_no_err(ele.to_string() + "-if-available"),
             Format {
-                format: ele.to_string(),
+                format: kind.clone(),
                 suffix: Suffix::IfAvailable
             }
         );
         assert_eq!(
             Format::from_string(ele.to_string() + "-if-available").unwrap(),
             Format {
-                format: ele.to_string(),
+                format: kind.clone(),
                 suffix: Suffix::IfAvailable
             }
         );
@@ -42,24 +50,2107 @@ fn format_from_string() {
         assert_eq!(
             Format::from_string_no_err(ele.to_string() + "-only"),
             Format {
-                format: ele.to_string(),
+                format: kind.clone(),
                 suffix: Suffix::Only
             }
         );
         assert_eq!(
             Format::from_string(ele.to_string() + "-only").unwrap(),
             Format {
-                format: ele.to_string(),
+                format: kind.clone(),
                 suffix: Suffix::Only
             }
         );
     }
 }
 
+#[test]
+#[allow(deprecated)]
+fn format_from_str_shim() {
+    assert_eq!(
+        Format::from_str("gz-only").unwrap(),
+        "gz-only".parse::<Format>().unwrap()
+    );
+    assert!(Format::from_str("zstd").is_err());
+}
+
 proptest! {
     #[test]
     fn format_from_string_handles_utf8(s in "\\PC*") {
         Format::from_string_no_err(s.clone());
+        Format::parse_lenient(&s);
         let _ = Format::from_string(s);
     }
+
+    #[test]
+    fn format_display_round_trips(s in "(msi|pkg|gz|xz)(-only|-if-available)?") {
+        let format = s.parse::<Format>().unwrap();
+        prop_assert_eq!(format.to_string().parse::<Format>().unwrap(), format);
+    }
+}
+
+#[test]
+fn format_display() {
+    assert_eq!(
+        "msi-if-available".parse::<Format>().unwrap().to_string(),
+        "msi-if-available"
+    );
+    assert_eq!(
+        "msi".parse::<Format>().unwrap().to_string(),
+        "msi-if-available"
+    );
+    assert_eq!("gz-only".parse::<Format>().unwrap().to_string(), "gz-only");
+    assert_eq!(Format::parse_lenient("zstd-only").format, "zstd");
+    assert!(Format::parse_lenient("zstd-only").to_format().is_err());
+    assert_eq!(
+        Format::from_str_no_err("zstd-only"),
+        Format {
+            format: FormatKind::Gz,
+            suffix: Suffix::Only
+        }
+    );
+    assert_eq!(
+        toml::to_string(&HashMap::from([(
+            "formats",
+            vec!["xz-only".parse::<Format>().unwrap()]
+        )]))
+        .unwrap(),
+        "formats = [\"xz-only\"]\n"
+    ); // fmt writes formats back exactly as they were written
+    let config = "[pkg]\ntoolchains = []\n\n[pkg.crates]\n\n[pkg.formats]\nlinux = [\n    \"gz-if-available\",\n    \"xz\",\n]\n";
+    assert_eq!(
+        normalize::normalize(config, Path::new("rust-config.toml")).unwrap(),
+        config
+    );
+}
+
+#[test]
+fn parse_str_reports_span() {
+    let config = "[pkg]\ntoolchains = 5\ncrates = {}\nformats = {}\n";
+    let err = parse_str(config, Path::new("rust-config.toml")).unwrap_err();
+    assert_eq!(err.line_col(), Some((2, 14)));
+    assert_eq!(
+        err.to_string(),
+        "rust-config.toml:2:14: pkg.toolchains: invalid type: integer `5`, expected a sequence"
+    );
+    assert_eq!(
+        err.render(),
+        "error: pkg.toolchains: invalid type: integer `5`, expected a sequence\n --> rust-config.toml:2:14\n  |\n2 | toolchains = 5\n  |              ^"
+    );
+}
+
+#[test]
+fn parse_str_reports_syntax_errors() {
+    let err = parse_str("[pkg\n", Path::new("rust-config.toml")).unwrap_err();
+    assert!(err.span.is_some());
+    assert_eq!(err.line_col().unwrap().0, 1);
+}
+
+#[test]
+fn parse_file_reports_missing_file() {
+    let err = parse_file(Path::new("does/not/exist.toml")).unwrap_err();
+    assert!(err.span.is_none());
+    assert!(err.render().starts_with("error: failed to read file"));
+}
+
+#[test]
+fn parse_example_config() {
+    let config = parse_file(Path::new("rust-config.toml")).unwrap();
+    assert!(config.contains_key("x64_package_linux_rust_pkg_gen"));
+}
+
+#[test]
+fn validate_example_config() {
+    let config = parse_file(Path::new("rust-config.toml")).unwrap();
+    assert_eq!(validate::validate(&config), vec![]);
+}
+
+#[test]
+fn validate_reports_every_problem() {
+    let config = r#"
+[pkg]
+toolchains = [
+    { channel = "sometimes", components = [], crate-id = "missing", platforms = [
+        "x86_64-unknown-linux-gnu",
+        "not-a-target",
+    ], targets = ["also-not-a-target"], format-map = { "x86_64-unknown-linux-gnu" = "linux", "aarch64-apple-darwin" = "nope" } },
+]
+
+[pkg.formats]
+linux = ["msi-only", "pkg"]
+empty = []
+
+[pkg.crates]
+"#;
+    let config = parse_str(config, Path::new("rust-config.toml")).unwrap();
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(|err| err.to_string())
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: format list `empty` is empty",
+            "pkg: format list `linux` can only contain `pkg` as its first element",
+            "pkg.toolchains[0]: unknown channel `sometimes`: invalid channel sometimes",
+            "pkg.toolchains[0]: crate-id `missing` isn't in `crates`",
+            "pkg.toolchains[0]: unknown platform `not-a-target`",
+            "pkg.toolchains[0]: unknown target `also-not-a-target`",
+            "pkg.toolchains[0]: format-map contains `aarch64-apple-darwin`, which isn't in `platforms`",
+            "pkg.toolchains[0]: format-map refers to format list `nope`, which isn't in `formats`",
+            "pkg.toolchains[0]: platform `x86_64-unknown-linux-gnu` is not windows but format list `linux` requires msi",
+            "pkg.toolchains[0]: platform `not-a-target` has no entry in format-map",
+        ]
+    );
+}
+
+#[test]
+fn format_deserialize_is_strict() {
+    for (value, message) in [
+        (
+            "zstd-only",
+            "invalid format `zstd-only`: invalid format zstd",
+        ),
+        (
+            "gz-sometimes",
+            "invalid format `gz-sometimes`: invalid suffix sometimes",
+        ),
+    ] {
+        let config = format!(
+            "[pkg]\ntoolchains = []\ncrates = {{}}\n[pkg.formats]\nlinux = [\"gz\", \"{value}\"]\n"
+        );
+        let err = parse_str(&config, Path::new("rust-config.toml")).unwrap_err();
+        assert_eq!(err.key.as_deref(), Some("pkg.formats.linux[1]"));
+        assert_eq!(err.message, message);
+        assert_eq!(err.line_col(), Some((5, 16)));
+    }
+}
+
+#[test]
+fn to_toml_string_round_trips() {
+    let config = parse_file(Path::new("rust-config.toml")).unwrap();
+    let serialized = to_toml_string(&config).unwrap();
+    let reparsed = parse_str(&serialized, Path::new("rust-config.toml")).unwrap();
+    assert_eq!(to_toml_string(&reparsed).unwrap(), serialized);
+}
+
+#[test]
+fn normalize_keeps_comments() {
+    let config = r#"# the package for the build machines
+[pkg]
+toolchains = [
+    { channel = "stable", components = ["rustc"], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = { "x86_64-unknown-linux-gnu" = "linux" } },
+]
+
+[pkg.formats]
+# only tarballs on linux
+linux = [
+    "gz-only", # nothing else works
+    "xz",
+] # end of formats
+
+[pkg.crates.base]
+serde = { features = ["derive"], version = "1" }
+anyhow = "1" # errors
+"#;
+    let normalized = normalize::normalize(config, Path::new("rust-config.toml")).unwrap();
+    assert_eq!(
+        normalized,
+        r#"# the package for the build machines
+[[pkg.toolchains]]
+channel = "stable"
+components = ["rustc"]
+crate-id = "base"
+platforms = ["x86_64-unknown-linux-gnu"]
+targets = []
+
+[pkg.toolchains.format-map]
+x86_64-unknown-linux-gnu = "linux"
+
+[pkg.crates.base]
+anyhow = "1" # errors
+
+[pkg.crates.base.serde]
+version = "1"
+features = ["derive"]
+
+[pkg.formats]
+# only tarballs on linux
+linux = [
+    "gz-only", # nothing else works
+    "xz",
+] # end of formats
+"#
+    );
+    assert_eq!(
+        normalize::normalize(&normalized, Path::new("rust-config.toml")).unwrap(),
+        normalized
+    );
+}
+
+#[test]
+fn toolchain_spec_manifest_path() {
+    for (spec, path) in [
+        ("stable", "dist/channel-rust-stable.toml"),
+        ("nightly", "dist/channel-rust-nightly.toml"),
+        ("1.83.0", "dist/channel-rust-1.83.0.toml"),
+        ("1.82", "dist/channel-rust-1.82.toml"),
+        (
+            "nightly-2024-11-30",
+            "dist/2024-11-30/channel-rust-nightly.toml",
+        ),
+        ("beta-2024-12-01", "dist/2024-12-01/channel-rust-beta.toml"),
+    ] {
+        let parsed = spec.parse::<channel::ToolchainSpec>().unwrap();
+        assert_eq!(parsed.manifest_path(), path);
+        assert_eq!(parsed.to_string(), spec);
+    }
+    for spec in [
+        "unstable",
+        "1",
+        "1.83.0.1",
+        "1.x",
+        "nightly-2024-13-01",
+        "nightly-yesterday",
+    ] {
+        assert!(spec.parse::<channel::ToolchainSpec>().is_err(), "{spec}");
+    }
+}
+
+#[test]
+fn resolved_toolchain_from_manifest() {
+    let manifest: toml::Value = toml::from_str(
+        "date = \"2024-11-28\"\n[pkg.rust]\nversion = \"1.83.0 (90b35a623 2024-11-26)\"\n",
+    )
+    .unwrap();
+    let resolved =
+        channel::ResolvedToolchain::from_manifest(&"stable".parse().unwrap(), &manifest).unwrap();
+    assert_eq!(resolved.date, "2024-11-28");
+    assert_eq!(resolved.version, "1.83.0 (90b35a623 2024-11-26)");
+    assert_eq!(resolved.artifact_channel, "1.83.0");
+
+    let resolved = channel::ResolvedToolchain::from_manifest(
+        &"nightly-2024-11-28".parse().unwrap(),
+        &manifest,
+    )
+    .unwrap();
+    assert_eq!(resolved.artifact_channel, "nightly");
+}
+
+const INHERITANCE_CONFIG: &str = r#"
+[defaults]
+formats = { linux = ["gz-only"] }
+
+[defaults.toolchain]
+components = ["rustc", "cargo"]
+platforms = ["x86_64-unknown-linux-gnu"]
+targets = []
+format-map = { "x86_64-unknown-linux-gnu" = "linux" }
+
+[base]
+toolchains = [{ channel = "stable", crate-id = "base" }]
+crates = { base = { serde = "1" } }
+
+[web]
+extends = "base"
+crates = { base = { axum = "0.7" } }
+
+[nightly]
+toolchains = [{ extends = "base", channel = "nightly", components = ["rustc"] }]
+crates = { base = {} }
+formats = { linux = ["xz"] }
+"#;
+
+#[test]
+fn inheritance() {
+    let config = parse_str(INHERITANCE_CONFIG, Path::new("rust-config.toml")).unwrap();
+    assert_eq!(config.len(), 3);
+    assert_eq!(validate::validate(&config), vec![]);
+
+    let web = &config["web"];
+    assert_eq!(web.toolchains[0].channel, "stable");
+    assert_eq!(web.toolchains[0].components, vec!["rustc", "cargo"]);
+    assert_eq!(web.crates["base"].len(), 2);
+    assert_eq!(web.formats["linux"][0].to_string(), "gz-only");
+
+    let nightly = &config["nightly"];
+    assert_eq!(nightly.toolchains[0].channel, "nightly");
+    assert_eq!(nightly.toolchains[0].crate_id, "base");
+    assert_eq!(nightly.toolchains[0].components, vec!["rustc"]);
+    assert_eq!(
+        nightly.toolchains[0].platforms,
+        vec!["x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(nightly.formats["linux"][0].to_string(), "xz-if-available");
+}
+
+#[test]
+fn inheritance_errors() {
+    for (config, key, message) in [
+        (
+            "[a]\nextends = \"b\"\n[b]\nextends = \"a\"\n",
+            "b.extends",
+            "`a` inherits from itself (through `a` -> `b`)",
+        ),
+        (
+            "[a]\nextends = \"missing\"\n",
+            "a.extends",
+            "package `missing` doesn't exist",
+        ),
+        (
+            "[a]\ntoolchains = [{}, {}]\n[b]\ntoolchains = [{ extends = \"a\" }]\n",
+            "b.toolchains[0].extends",
+            "package `a` has 2 toolchains, use `a[<index>]` to pick one",
+        ),
+        (
+            "[a]\ntoolchains = [{}]\n[b]\ntoolchains = [{ extends = \"a[3]\" }]\n",
+            "b.toolchains[0].extends",
+            "toolchain `a[3]` doesn't exist",
+        ),
+    ] {
+        let err = parse_str(config, Path::new("rust-config.toml")).unwrap_err();
+        assert_eq!(err.key.as_deref(), Some(key));
+        assert_eq!(err.message, message);
+        assert!(err.span.is_some());
+    }
+}
+
+#[test]
+fn inherited_errors_point_at_the_package() {
+    let config =
+        "[defaults.toolchain]\ncomponents = 5\n\n[pkg]\ntoolchains = [{ channel = \"stable\" }]\n";
+    let err = parse_str(config, Path::new("rust-config.toml")).unwrap_err();
+    assert_eq!(err.key.as_deref(), Some("pkg.toolchains[0].components"));
+    assert_eq!(err.line_col(), Some((5, 15)));
+}
+
+#[test]
+fn expanded_config_expands_inheritance() {
+    let expanded = normalize::expanded(INHERITANCE_CONFIG, Path::new("rust-config.toml")).unwrap();
+    assert!(!expanded.contains("[defaults"));
+    assert!(!expanded.contains("extends"));
+    assert!(expanded.starts_with("[[base.toolchains]]\n"));
+    assert!(expanded.contains("[[web.toolchains]]\nchannel = \"stable\"\n"));
+    assert_eq!(
+        normalize::expanded(&expanded, Path::new("rust-config.toml")).unwrap(),
+        expanded
+    );
+}
+
+#[test]
+fn normalize_keeps_inheritance() {
+    let normalized =
+        normalize::normalize(INHERITANCE_CONFIG, Path::new("rust-config.toml")).unwrap();
+    assert!(normalized.starts_with("[defaults.toolchain]\n"));
+    assert!(normalized.contains("[web]\nextends = \"base\"\n"));
+    assert!(
+        normalized.contains("[[nightly.toolchains]]\nextends = \"base\"\nchannel = \"nightly\"\n")
+    );
+    assert_eq!(
+        normalize::normalize(&normalized, Path::new("rust-config.toml")).unwrap(),
+        normalized
+    );
+}
+
+#[test]
+fn normalize_keeps_includes_and_variables() {
+    let config = r#"include = ["crates.toml"]
+
+[pkg]
+toolchains = [
+    { channel = "${RUST_PKG_GEN_TEST_UNSET_CHANNEL:-stable}", components = ["rustc"], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = { "x86_64-unknown-linux-gnu" = "linux" } },
+]
+patches = { base = { log = { path = "../forks/log" } } }
+formats = { linux = ["gz"] }
+"#;
+    let dir = write_files(
+        "normalize-include",
+        &[
+            ("rust-config.toml", config),
+            ("crates.toml", "[crates.base]\nlog = \"0.4\"\n"),
+        ],
+    );
+    let path = dir.join("rust-config.toml");
+    let normalized = normalize::normalize(config, &path).unwrap();
+    assert!(normalized.starts_with("include = [\"crates.toml\"]\n"));
+    assert!(normalized.contains("channel = \"${RUST_PKG_GEN_TEST_UNSET_CHANNEL:-stable}\"\n"));
+    assert!(normalized.contains("[pkg.patches.base.log]\npath = \"../forks/log\"\n"));
+    assert!(!normalized.contains("[pkg.crates"));
+    assert_eq!(
+        normalize::normalize(&normalized, &path).unwrap(),
+        normalized
+    );
+}
+
+/// Writes `files` to a new directory in the temp dir and returns its path.
+fn write_files(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust-pkg-gen-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, contents) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+const INCLUDING_CONFIG: &str = r#"include = ["crates/*.toml"]
+
+[pkg]
+include = ["formats.toml"]
+toolchains = [{ channel = "stable", components = ["rustc"], crate-id = "web", platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = { "x86_64-unknown-linux-gnu" = "linux" } }]
+"#;
+
+#[test]
+fn include() {
+    let dir = write_files(
+        "include",
+        &[
+            ("rust-config.toml", INCLUDING_CONFIG),
+            ("crates/base.toml", "[crates.base]\nserde = \"1\"\n"),
+            (
+                "crates/web.toml",
+                "include = [\"../more/*.toml\"]\n[crates.web]\naxum = \"0.7\"\n",
+            ),
+            ("more/cli.toml", "[crates.cli]\nclap = \"4\"\n"),
+            ("formats.toml", "[formats]\nlinux = [\"gz-only\"]\n"),
+        ],
+    );
+    let config = parse_file(&dir.join("rust-config.toml")).unwrap();
+    let pkg = &config["pkg"];
+    let mut ids: Vec<&String> = pkg.crates.keys().collect();
+    ids.sort();
+    assert_eq!(ids, vec!["base", "cli", "web"]);
+    assert_eq!(pkg.formats["linux"][0].to_string(), "gz-only");
+    assert_eq!(validate::validate(&config), vec![]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_errors() {
+    let dir = write_files(
+        "include-errors",
+        &[
+            ("rust-config.toml", INCLUDING_CONFIG),
+            ("crates/a.toml", "[crates.web]\nserde = \"1\"\n"),
+            ("crates/b.toml", "[crates.web]\naxum = 5\n"),
+            ("formats.toml", "[formats]\nlinux = [\"gz-only\"]\n"),
+        ],
+    );
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.path, dir.join("crates/b.toml"));
+    assert_eq!(err.key.as_deref(), Some("crates.web"));
+    assert_eq!(err.message, "crate list `web` is defined more than once");
+    assert_eq!(err.notes.len(), 1);
+    assert_eq!(err.notes[0].path, dir.join("crates/a.toml"));
+    assert_eq!(err.notes[0].line_col(), Some((1, 1)));
+    assert!(err
+        .render()
+        .contains("\nnote: crates.web: first defined here\n"));
+
+    // errors in included files point at the included file
+    fs::remove_file(dir.join("crates/a.toml")).unwrap();
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.path, dir.join("crates/b.toml"));
+    assert_eq!(err.key.as_deref(), Some("crates.web.axum"));
+    assert_eq!(err.line_col(), Some((2, 8)));
+
+    fs::write(dir.join("formats.toml"), "[pkg]\n").unwrap();
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.path, dir.join("formats.toml"));
+    assert_eq!(err.key.as_deref(), Some("pkg"));
+
+    fs::remove_file(dir.join("formats.toml")).unwrap();
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.path, dir.join("rust-config.toml"));
+    assert_eq!(err.key.as_deref(), Some("pkg.include[0]"));
+    assert!(err.message.ends_with("formats.toml` doesn't exist"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn include_conflicts_with_package() {
+    let config =
+        INCLUDING_CONFIG.replace("[pkg]\n", "[pkg]\ncrates = { web = { serde = \"1\" } }\n");
+    let dir = write_files(
+        "include-package",
+        &[
+            ("rust-config.toml", &config),
+            ("crates/web.toml", "[crates.web]\naxum = \"0.7\"\n"),
+            ("formats.toml", "[formats]\nlinux = [\"gz-only\"]\n"),
+        ],
+    );
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.path, dir.join("rust-config.toml"));
+    assert_eq!(err.key.as_deref(), Some("pkg.crates.web"));
+    assert_eq!(err.message, "crate list `web` is defined more than once");
+    assert_eq!(err.notes.len(), 1);
+    assert_eq!(err.notes[0].path, dir.join("crates/web.toml"));
+    assert_eq!(err.notes[0].key.as_deref(), Some("crates.web"));
+
+    // the same goes for a list from the package's own include
+    fs::write(
+        dir.join("formats.toml"),
+        "[formats]\nlinux = [\"gz-only\"]\n[crates.web]\nclap = \"4\"\n",
+    )
+    .unwrap();
+    let err = parse_file(&dir.join("rust-config.toml")).unwrap_err();
+    assert_eq!(err.message, "crate list `web` is defined more than once");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+const INTERPOLATED_CONFIG: &str = r#"[pkg]
+toolchains = [{ channel = "${RPG_TEST_CHANNEL:-stable}", components = ["rustc"], crate-id = "base", platforms = ["${RPG_TEST_PLATFORM}"], targets = [], format-map = { "x86_64-unknown-linux-gnu" = "linux" } }]
+crates = { base = { serde = "$${RPG_TEST_PLATFORM}" } }
+formats = { linux = ["gz"] }
+"#;
+
+#[test]
+fn interpolation() {
+    let mut options = LoadOptions::default();
+    options.vars.insert(
+        "RPG_TEST_PLATFORM".to_string(),
+        "x86_64-unknown-linux-gnu".to_string(),
+    );
+    let config =
+        parse_str_with(INTERPOLATED_CONFIG, Path::new("rust-config.toml"), &options).unwrap();
+    let pkg = &config["pkg"];
+    assert_eq!(pkg.toolchains[0].channel, "stable");
+    assert_eq!(
+        pkg.toolchains[0].platforms,
+        vec!["x86_64-unknown-linux-gnu"]
+    );
+    assert!(matches!(
+        &pkg.crates["base"]["serde"],
+        Crate::Version(version) if version == "${RPG_TEST_PLATFORM}"
+    ));
+
+    options
+        .vars
+        .insert("RPG_TEST_CHANNEL".to_string(), "nightly".to_string());
+    let config =
+        parse_str_with(INTERPOLATED_CONFIG, Path::new("rust-config.toml"), &options).unwrap();
+    assert_eq!(config["pkg"].toolchains[0].channel, "nightly");
+
+    let err = parse_str(INTERPOLATED_CONFIG, Path::new("rust-config.toml")).unwrap_err();
+    assert_eq!(err.key.as_deref(), Some("pkg.toolchains[0].platforms[0]"));
+    assert_eq!(
+        err.message,
+        "environment variable `RPG_TEST_PLATFORM` isn't set"
+    );
+    assert_eq!(err.line_col(), Some((2, 114)));
+}
+
+#[test]
+fn overrides() {
+    let options = LoadOptions {
+        overrides: vec![
+            "pkg.toolchains[0].channel=1.83.0".parse().unwrap(),
+            r#"pkg.toolchains[0].platforms=["x86_64-unknown-linux-gnu"]"#
+                .parse()
+                .unwrap(),
+            r#"pkg.formats."linux"=["xz"]"#.parse().unwrap(),
+        ],
+        allow_undefined: true,
+        ..LoadOptions::default()
+    };
+    let config =
+        parse_str_with(INTERPOLATED_CONFIG, Path::new("rust-config.toml"), &options).unwrap();
+    let pkg = &config["pkg"];
+    assert_eq!(pkg.toolchains[0].channel, "1.83.0");
+    assert_eq!(pkg.formats["linux"][0].to_string(), "xz-if-available");
+
+    for (over, message) in [
+        (
+            "pkg.toolchains[1].channel=beta",
+            "--set pkg.toolchains[1].channel=\"beta\": `pkg.toolchains[1]` doesn't exist",
+        ),
+        (
+            "pkg.toolchains.channel=beta",
+            "--set pkg.toolchains.channel=\"beta\": `pkg.toolchains` isn't a table",
+        ),
+    ] {
+        let options = LoadOptions {
+            overrides: vec![over.parse().unwrap()],
+            allow_undefined: true,
+            ..LoadOptions::default()
+        };
+        let err = parse_str_with(INTERPOLATED_CONFIG, Path::new("rust-config.toml"), &options)
+            .unwrap_err();
+        assert_eq!(err.message, message);
+    }
+    for over in ["pkg", "pkg..a=b", "pkg[x]=b", "=b", "\"pkg=b"] {
+        assert!(over.parse::<interpolate::Override>().is_err(), "{over}");
+    }
+}
+
+const METADATA: &str = r#"toolchains = [{ channel = "stable", components = ["rustc"], platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = { "x86_64-unknown-linux-gnu" = "linux" } }]
+formats = { linux = ["gz"] }
+"#;
+
+#[test]
+fn cargo_metadata() {
+    let dir = write_files(
+        "metadata",
+        &[
+            (
+                "Cargo.toml",
+                &format!(
+                    r#"[workspace]
+members = ["crates/*"]
+exclude = ["crates/excluded"]
+
+[workspace.dependencies]
+serde = {{ version = "1", features = ["derive"] }}
+anyhow = "1"
+
+[workspace.metadata.rust-pkg-gen]
+{METADATA}"#
+                ),
+            ),
+            (
+                "crates/app/Cargo.toml",
+                r#"[package]
+name = "app"
+
+[dependencies]
+lib = { path = "../lib" }
+serde = { workspace = true, features = ["rc"] }
+clap = "4"
+
+[target.'cfg(windows)'.dependencies]
+winapi = "0.3"
+"#,
+            ),
+            (
+                "crates/lib/Cargo.toml",
+                r#"[package]
+name = "lib"
+
+[dependencies]
+anyhow.workspace = true
+local = { path = "../../local" }
+published = { path = "../../published", version = "0.2" }
+
+[build-dependencies]
+cc = "1"
+
+[dev-dependencies]
+proptest = "1"
+"#,
+            ),
+            (
+                "crates/excluded/Cargo.toml",
+                "[package]\nname = \"excluded\"\n[dependencies]\nrand = \"0.8\"\n",
+            ),
+            ("local/Cargo.toml", "[package]\nname = \"local\"\n"),
+        ],
+    );
+    let config = parse_file(&dir.join("Cargo.toml")).unwrap();
+    let name = dir.file_name().unwrap().to_str().unwrap();
+    let pkg = &config[name];
+    assert_eq!(pkg.toolchains[0].crate_id, name);
+    let crates = &pkg.crates[name];
+    let mut names: Vec<&String> = crates.keys().collect();
+    names.sort();
+    // path-only dependencies and members are left out, build- and
+    // dev-dependencies aren't
+    assert_eq!(
+        names,
+        vec![
+            "anyhow",
+            "cc",
+            "clap",
+            "proptest",
+            "published",
+            "serde",
+            "winapi"
+        ]
+    );
+    assert!(matches!(
+        &crates["serde"],
+        Crate::Detailed { version: Some(version), features: Some(features), .. }
+            if version == "1" && features == &["derive", "rc"]
+    ));
+    assert!(matches!(
+        &crates["published"],
+        Crate::Detailed { version: Some(version), path: None, .. } if version == "0.2"
+    ));
+    assert!(matches!(
+        &crates["winapi"],
+        Crate::Detailed { version: Some(version), target: Some(target), .. }
+            if version == "0.3" && target == "cfg(windows)"
+    ));
+    assert_eq!(validate::validate(&config), vec![]);
+
+    fs::write(
+        dir.join("crates/app/Cargo.toml"),
+        format!("[package]\nname = \"app\"\n\n[package.metadata.rust-pkg-gen]\n{METADATA}"),
+    )
+    .unwrap();
+    let config = parse_file(&dir.join("crates/app/Cargo.toml")).unwrap();
+    assert!(config["app"].crates["app"].is_empty());
+
+    // a member of the workspace is skipped even with a version
+    fs::write(
+        dir.join("crates/app/Cargo.toml"),
+        format!("[package]\nname = \"app\"\n\n[dependencies]\nlib = {{ path = \"../lib\", version = \"0.1\" }}\nclap = \"4\"\n\n[package.metadata.rust-pkg-gen]\n{METADATA}"),
+    )
+    .unwrap();
+    let config = parse_file(&dir.join("crates/app/Cargo.toml")).unwrap();
+    let mut names: Vec<&String> = config["app"].crates["app"].keys().collect();
+    names.sort();
+    assert_eq!(names, vec!["clap"]);
+
+    fs::write(
+        dir.join("crates/app/Cargo.toml"),
+        "[package]\nname = \"app\"\n\n[package.metadata.rust-pkg-gen]\ntoolchains = 5\n",
+    )
+    .unwrap();
+    let err = parse_file(&dir.join("crates/app/Cargo.toml")).unwrap_err();
+    assert_eq!(
+        err.key.as_deref(),
+        Some("package.metadata.rust-pkg-gen.toolchains")
+    );
+    assert_eq!(err.line_col(), Some((5, 14)));
+
+    fs::write(
+        dir.join("crates/app/Cargo.toml"),
+        "[package]\nname = \"app\"\n",
+    )
+    .unwrap();
+    let err = parse_file(&dir.join("crates/app/Cargo.toml")).unwrap_err();
+    assert_eq!(
+        err.message,
+        "no [package.metadata.rust-pkg-gen] or [workspace.metadata.rust-pkg-gen] table"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn init_from_project() {
+    let dir = write_files(
+        "init",
+        &[
+            ("Cargo.toml", "[package]\nname = \"app\"\n"),
+            (
+                "rust-toolchain.toml",
+                "[toolchain]\nchannel = \"1.83.0\"\ncomponents = [\"clippy\", \"cargo\"]\ntargets = [\"wasm32-unknown-unknown\"]\n",
+            ),
+            (
+                "Cargo.lock",
+                r#"version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+
+[[package]]
+name = "serde"
+version = "1.0.100"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "serde"
+version = "1.0.215"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "local"
+version = "0.1.0"
+source = "git+https://example.com/local#abc"
+"#,
+            ),
+        ],
+    );
+    let (name, package) = init::package_from_project(&dir, "x86_64-pc-windows-msvc").unwrap();
+    assert_eq!(name, "app");
+    let toolchain = &package.toolchains[0];
+    assert_eq!(toolchain.channel, "1.83.0");
+    assert_eq!(
+        toolchain.components,
+        vec!["rustc", "cargo", "rust-std", "clippy"]
+    );
+    assert_eq!(toolchain.targets, vec!["wasm32-unknown-unknown"]);
+    assert_eq!(toolchain.platforms, vec!["x86_64-pc-windows-msvc"]);
+    assert_eq!(package.crates["app"].len(), 1);
+    let Crate::Versions(versions) = &package.crates["app"]["serde"] else {
+        panic!("both locked versions of serde should be kept");
+    };
+    assert!(matches!(
+        versions.as_slice(),
+        [Crate::Version(newest), Crate::Version(oldest)]
+            if newest == "=1.0.215" && oldest == "=1.0.100"
+    ));
+    let formats: Vec<String> = package.formats["x86_64-pc-windows-msvc"]
+        .iter()
+        .map(Format::to_string)
+        .collect();
+    assert_eq!(formats, vec!["msi-if-available", "gz-if-available"]);
+    let config = RustConfig::from([(name, package)]);
+    assert_eq!(validate::validate(&config), vec![]);
+
+    fs::remove_file(dir.join("rust-toolchain.toml")).unwrap();
+    fs::write(dir.join("rust-toolchain"), "nightly-2024-11-30\n").unwrap();
+    let (_, package) = init::package_from_project(&dir, "x86_64-unknown-linux-gnu").unwrap();
+    assert_eq!(package.toolchains[0].channel, "nightly-2024-11-30");
+
+    fs::remove_file(dir.join("rust-toolchain")).unwrap();
+    fs::write(
+        dir.join("rust-toolchain.toml"),
+        "[toolchain]\nchannel = \"stable\"\nprofile = \"minimal\"\ncomponents = [\"clippy\"]\n",
+    )
+    .unwrap();
+    let (_, package) = init::package_from_project(&dir, "x86_64-unknown-linux-gnu").unwrap();
+    assert_eq!(package.toolchains[0].profile.as_deref(), Some("minimal"));
+    assert_eq!(package.toolchains[0].components, vec!["clippy"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn manifest_availability() {
+    let manifest: toml::Value = toml::from_str(
+        r#"
+[pkg.rust.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rustc.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rustc.target.x86_64-pc-windows-msvc]
+available = true
+[pkg.rustc.target.x86_64-apple-darwin]
+available = false
+[pkg.rust-std.target.wasm32-unknown-unknown]
+available = true
+[pkg.rust-std.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rust-src.target."*"]
+available = true
+[pkg.clippy-preview.target.x86_64-unknown-linux-gnu]
+available = true
+
+[renames.clippy]
+to = "clippy-preview"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        channel::available_targets(&manifest, "rustc"),
+        vec!["x86_64-pc-windows-msvc", "x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(
+        channel::available_targets(&manifest, "rust-std"),
+        vec!["wasm32-unknown-unknown", "x86_64-unknown-linux-gnu"]
+    );
+    assert!(channel::available_targets(&manifest, "rust-src").is_empty());
+    assert_eq!(
+        channel::available_components(&manifest, &["x86_64-unknown-linux-gnu".to_string()]),
+        vec!["clippy", "rust-src", "rust-std", "rustc"]
+    );
+    assert_eq!(
+        channel::available_components(
+            &manifest,
+            &[
+                "x86_64-unknown-linux-gnu".to_string(),
+                "x86_64-pc-windows-msvc".to_string()
+            ]
+        ),
+        vec!["rust-src", "rustc"]
+    );
+}
+
+#[test]
+fn component_profiles() {
+    let mut manifest = String::from(
+        r#"
+[profiles]
+minimal = ["rustc", "cargo", "rust-std", "rust-mingw"]
+default = ["rustc", "cargo", "rust-std", "rust-mingw", "rust-docs", "clippy-preview"]
+
+[renames.clippy]
+to = "clippy-preview"
+
+[pkg.rust-src.target."*"]
+available = true
+url = "https://static.rust-lang.org/dist/2024-11-28/rust-src-1.83.0.tar.gz"
+"#,
+    );
+    let linux = "x86_64-unknown-linux-gnu";
+    let windows = "x86_64-pc-windows-gnu";
+    for (package, file, targets) in [
+        ("rustc", "rustc", [linux, windows].as_slice()),
+        ("cargo", "cargo", &[linux, windows]),
+        ("rust-std", "rust-std", &[linux, windows]),
+        ("rust-mingw", "rust-mingw", &[windows]),
+        ("rust-docs", "rust-docs", &[linux, windows]),
+        ("clippy-preview", "clippy", &[linux, windows]),
+    ] {
+        for target in targets {
+            manifest += &format!(
+                "[pkg.{package}.target.{target}]\navailable = true\nurl = \"https://static.rust-lang.org/dist/2024-11-28/{file}-1.83.0-{target}.tar.gz\"\n"
+            );
+        }
+    }
+    let manifest: toml::Value = toml::from_str(&manifest).unwrap();
+
+    let platforms = [linux, windows];
+    let packages = channel::resolve_components(
+        &manifest,
+        Some("default"),
+        &["rust-src", "clippy"],
+        &platforms,
+    )
+    .unwrap();
+    let install = channel::install_components(&manifest, &packages, &platforms, "1.83.0");
+    assert_eq!(
+        install,
+        BTreeMap::from([
+            (
+                linux.to_string(),
+                vec!["cargo", "clippy", "rust-docs", "rust-std", "rustc"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            ),
+            (
+                windows.to_string(),
+                vec!["clippy", "rust-docs", "rust-mingw"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect()
+            ),
+        ])
+    );
+    // every component the install scripts look for on a platform is the
+    // name of a tarball download_all downloads for it
+    for (platform, names) in &install {
+        for name in names {
+            let file = format!("{}-1.83.0-{}.tar.gz", name, platform);
+            let package = packages
+                .iter()
+                .find(|package| {
+                    manifest["pkg"][package.as_str()]["target"]
+                        .get(platform)
+                        .and_then(|target| target.get("url"))
+                        .and_then(toml::Value::as_str)
+                        .is_some_and(|url| url.ends_with(&format!("/{}", file)))
+                })
+                .unwrap_or_else(|| panic!("nothing downloads {}", file));
+            assert!(channel::downloads_tarball(
+                package,
+                platform,
+                &platforms,
+                &[] as &[&str]
+            ));
+        }
+    }
+    // the msi installs rustc on windows, but rust-std is still needed to
+    // cross compile to it
+    assert!(!channel::downloads_tarball(
+        "rustc",
+        windows,
+        &platforms,
+        &[] as &[&str]
+    ));
+    assert!(channel::downloads_tarball(
+        "rust-std",
+        windows,
+        &platforms,
+        &[] as &[&str]
+    ));
+    assert!(!channel::downloads_tarball(
+        "rustc",
+        "aarch64-unknown-linux-gnu",
+        &platforms,
+        &[] as &[&str]
+    ));
+
+    let linux = [linux];
+    assert_eq!(
+        channel::resolve_components(&manifest, Some("complete"), &["rustc"], &linux)
+            .unwrap_err()
+            .to_string(),
+        "manifest has no `complete` profile"
+    );
+
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [
+    { channel = "stable", profile = "default", crate-id = "base", platforms = [], targets = [], format-map = {} },
+    { channel = "stable", profile = "huge", components = ["rustc"], crate-id = "base", platforms = [], targets = [], format-map = {} },
+]
+crates = { base = {} }
+formats = {}
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let pkg = &config["pkg"];
+    assert_eq!(pkg.toolchains[0].profile.as_deref(), Some("default"));
+    assert!(pkg.toolchains[0].components.is_empty());
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec!["pkg.toolchains[1]: unknown profile `huge`(expected `minimal`, `default`, or `complete`)"]
+    );
+}
+
+#[test]
+fn target_patterns() {
+    let available = [
+        "aarch64-unknown-linux-musl",
+        "thumbv7em-none-eabi",
+        "thumbv7em-none-eabihf",
+        "x86_64-pc-windows-msvc",
+        "x86_64-unknown-linux-gnu",
+        "x86_64-unknown-linux-musl",
+    ];
+    let expand = |targets: &[&str]| {
+        targets::expand(
+            &targets.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            &available,
+        )
+    };
+    assert_eq!(
+        expand(&[
+            "tier1",
+            "*-unknown-linux-musl",
+            "thumbv7em-*",
+            "x86_64-unknown-linux-gnu"
+        ]),
+        Ok(vec![
+            "x86_64-pc-windows-msvc".to_string(),
+            "x86_64-unknown-linux-gnu".to_string(),
+            "aarch64-unknown-linux-musl".to_string(),
+            "x86_64-unknown-linux-musl".to_string(),
+            "thumbv7em-none-eabi".to_string(),
+            "thumbv7em-none-eabihf".to_string(),
+        ])
+    );
+    // only patterns are matched against what's available
+    assert_eq!(
+        expand(&["wasm32-unknown-unknown"]),
+        Ok(vec!["wasm32-unknown-unknown".to_string()])
+    );
+    assert_eq!(
+        expand(&["riscv*"]),
+        Err("target pattern `riscv*` doesn't match any target".to_string())
+    );
+    assert!(expand(&["[x86"]).is_err());
+    assert!(targets::TARGETS
+        .iter()
+        .all(|target| !targets::is_pattern(target)));
+
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [
+    { channel = "stable", components = [], crate-id = "base", platforms = ["tier1"], targets = ["thumbv7em-*"], format-map = { "*-windows-*" = "windows", "*" = "unix", "aarch64-apple-darwin" = "mac" } },
+    { channel = "stable", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gn?", "nope-*"], targets = ["powerpc*-windows-*"], format-map = { "aarch64-apple-*" = "unix" } },
+]
+crates = { base = {} }
+formats = { windows = ["msi", "gz"], unix = ["gz"], mac = ["pkg"] }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let toolchain = &config["pkg"].toolchains[0];
+    assert_eq!(
+        toolchain.format_for("x86_64-pc-windows-msvc"),
+        Some(&"windows".to_string())
+    );
+    assert_eq!(
+        toolchain.format_for("aarch64-unknown-linux-gnu"),
+        Some(&"unix".to_string())
+    );
+    assert_eq!(
+        toolchain.format_for("aarch64-apple-darwin"),
+        Some(&"mac".to_string())
+    );
+    let expanded = toolchain.expanded(&available).unwrap();
+    assert_eq!(
+        expanded.platforms,
+        vec!["x86_64-pc-windows-msvc", "x86_64-unknown-linux-gnu"]
+    );
+    assert_eq!(
+        expanded.targets,
+        vec!["thumbv7em-none-eabi", "thumbv7em-none-eabihf"]
+    );
+
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg.toolchains[1]: target pattern `nope-*` doesn't match any target",
+            "pkg.toolchains[1]: target pattern `powerpc*-windows-*` doesn't match any target",
+            "pkg.toolchains[1]: format-map contains `aarch64-apple-*`, which isn't in `platforms`",
+            "pkg.toolchains[1]: platform `x86_64-unknown-linux-gnu` has no entry in format-map",
+        ]
+    );
+}
+
+#[test]
+fn host_platform() {
+    let triple = |os, cpu, android, musl, userland_32| {
+        targets::triple_from_uname(os, cpu, android, musl, userland_32)
+    };
+    assert_eq!(
+        triple("Linux", "x86_64", false, false, false).as_deref(),
+        Some("x86_64-unknown-linux-gnu")
+    );
+    assert_eq!(
+        triple("Linux", "aarch64", false, true, false).as_deref(),
+        Some("aarch64-unknown-linux-musl")
+    );
+    assert_eq!(
+        triple("Linux", "armv7l", false, false, false).as_deref(),
+        Some("armv7-unknown-linux-gnueabihf")
+    );
+    assert_eq!(
+        triple("Linux", "armv7l", true, false, false).as_deref(),
+        Some("armv7-linux-androideabi")
+    );
+    assert_eq!(
+        triple("Linux", "x86_64", false, false, true).as_deref(),
+        Some("i686-unknown-linux-gnu")
+    );
+    assert_eq!(
+        triple("Linux", "aarch64", false, false, true).as_deref(),
+        Some("armv7-unknown-linux-gnueabihf")
+    );
+    assert_eq!(
+        triple("Darwin", "arm64", false, false, false).as_deref(),
+        Some("aarch64-apple-darwin")
+    );
+    assert_eq!(
+        triple("MINGW64_NT-10.0", "x86_64", false, false, false).as_deref(),
+        Some("x86_64-pc-windows-gnu")
+    );
+    assert_eq!(
+        triple("Linux", "riscv64", false, false, false).as_deref(),
+        Some("riscv64gc-unknown-linux-gnu")
+    );
+    assert_eq!(triple("Plan9", "x86_64", false, false, false), None);
+    assert_eq!(triple("Linux", "vax", false, false, false), None);
+
+    // every CPU maps to a real target
+    for cpu in [
+        "i686",
+        "x86_64",
+        "aarch64",
+        "armv6l",
+        "armv7l",
+        "ppc",
+        "ppc64",
+        "ppc64le",
+        "s390x",
+        "riscv64",
+        "loongarch64",
+    ] {
+        let triple = triple("Linux", cpu, false, false, false).unwrap();
+        assert!(targets::TARGETS.contains(&triple.as_str()), "{triple}");
+    }
+
+    let Some(host) = targets::host_triple() else {
+        return;
+    };
+    assert!(targets::is_pattern("host"));
+    assert_eq!(
+        targets::expand(&["host".to_string()], &targets::TARGETS),
+        Ok(vec![host.clone()])
+    );
+    assert_eq!(
+        targets::expand(&["host".to_string()], &["not-the-host"]),
+        Err(format!("the host platform `{host}` isn't available"))
+    );
+
+    // `platforms` defaults to the host
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [
+    { channel = "stable", components = [], crate-id = "base", targets = [], format-map = { host = "gz" } },
+]
+crates = { base = {} }
+formats = { gz = ["gz"] }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let toolchain = &config["pkg"].toolchains[0];
+    assert_eq!(toolchain.platforms, vec!["host"]);
+    assert_eq!(toolchain.format_for(&host), Some(&"gz".to_string()));
+    assert_eq!(
+        toolchain.expanded(&targets::TARGETS).unwrap().platforms,
+        vec![host]
+    );
+    assert_eq!(validate::validate(&config), vec![]);
+}
+
+#[test]
+fn manifest_targets() {
+    let manifest: toml::Value = toml::from_str(
+        r#"
+[pkg.rust.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rustc.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rust-std.target.x86_64-unknown-linux-gnu]
+available = true
+[pkg.rust-std.target.riscv64-new-target]
+available = true
+[pkg.rust-std.target.x86_64-apple-darwin]
+available = false
+[pkg.rust-src.target."*"]
+available = true
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        channel::components_by_target(&manifest),
+        BTreeMap::from([
+            ("*".to_string(), vec!["rust-src".to_string()]),
+            (
+                "riscv64-new-target".to_string(),
+                vec!["rust-std".to_string()]
+            ),
+            (
+                "x86_64-unknown-linux-gnu".to_string(),
+                vec!["rust-std".to_string(), "rustc".to_string()]
+            ),
+        ])
+    );
+
+    // a target newer than `targets::TARGETS` is fine once the manifest is known
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [
+    { channel = "nightly", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = ["riscv64-new-target"], format-map = { x86_64-unknown-linux-gnu = "gz" } },
+    { channel = "stable", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = ["riscv64-new-target"], format-map = { x86_64-unknown-linux-gnu = "gz" } },
+]
+crates = { base = {} }
+formats = { gz = ["gz"] }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let known_targets = HashMap::from([(
+        "nightly".to_string(),
+        channel::available_targets(&manifest, "rust-std"),
+    )]);
+    let errors: Vec<String> = validate::validate_with_targets(&config, &known_targets)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec!["pkg.toolchains[1]: unknown target `riscv64-new-target`"]
+    );
+}
+
+#[test]
+fn crate_dependency_spec() {
+    let crates: HashMap<String, Crate> = toml::from_str(
+        r#"
+serde = "1"
+tokio = { version = "1", default-features = false, features = ["rt"], optional = true }
+old = { version = "1", default_features = false }
+json = { package = "serde_json", version = "1", registry = "internal" }
+pinned = { git = "https://example.com/pinned.git", rev = "abc123" }
+"#,
+    )
+    .unwrap();
+    assert_eq!(crates["serde"].clone().serialize(), "\"1\"");
+    assert_eq!(
+        crates["tokio"].clone().serialize(),
+        "{ version = \"1\", features = [\"rt\"], default-features = false, optional = true }"
+    );
+    assert_eq!(
+        crates["old"].clone().serialize(),
+        "{ version = \"1\", default-features = false }"
+    );
+    assert_eq!(
+        crates["json"].clone().serialize(),
+        "{ version = \"1\", package = \"serde_json\", registry = \"internal\" }"
+    );
+    assert_eq!(
+        crates["pinned"].clone().serialize(),
+        "{ git = \"https://example.com/pinned.git\", rev = \"abc123\" }"
+    );
+    for krate in crates.values() {
+        let table: toml::Table =
+            toml::from_str(&format!("dep = {}", krate.clone().serialize())).unwrap();
+        assert!(table.contains_key("dep"));
+    }
+}
+
+#[test]
+fn validate_crates() {
+    let config = parse_str(
+        r#"[pkg]
+toolchains = []
+formats = {}
+
+[pkg.crates.base]
+nothing = { features = ["a"] }
+both = { path = "../both", git = "https://example.com/both.git" }
+branch = { version = "1", branch = "main" }
+refs = { git = "https://example.com/refs.git", tag = "v1", rev = "abc" }
+targeted = { version = "1", target = "windows" }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: crate `both` in crate list `base` can't have both a `path` and a `git`",
+            "pkg: crate `branch` in crate list `base` has a `branch` but no `git`",
+            "pkg: crate `nothing` in crate list `base` needs a `version`, `versions`, `path`, or `git`",
+            "pkg: crate `refs` in crate list `base` can only have one of `branch`, `tag`, and `rev`, but has `tag`, `rev`",
+            "pkg: crate `targeted` in crate list `base` has an invalid `target` `windows`(expected `cfg(...)` or a target triple)",
+        ]
+    );
+}
+
+#[test]
+fn generated_manifest_round_trips() {
+    let crates: HashMap<String, Crate> = toml::from_str(
+        r#"
+serde = "1"
+"with.dot" = { version = "0.1", features = ["a\"quoted\"", "back\\slash"] }
+local = { path = 'C:\crates\local' }
+tokio = { version = "1", default-features = false, optional = true, features = ["rt"] }
+pinned = { git = "https://example.com/pinned.git", branch = "main" }
+"#,
+    )
+    .unwrap();
+    let template = resources::TemplateAssets::get("template/template.Cargo.toml").unwrap();
+    let manifest = String::from_utf8_lossy(&template.data)
+        .replace("{?CRATES}", &dependencies(&crates))
+        .replace("{?PATCHES}", "");
+    let manifest: toml::Table = toml::from_str(&manifest).unwrap();
+    let parsed: HashMap<String, Crate> = manifest["dependencies"].clone().try_into().unwrap();
+    assert_eq!(parsed.len(), crates.len());
+    for (name, krate) in &crates {
+        assert_eq!(
+            parsed[name].to_toml().to_string(),
+            krate.to_toml().to_string()
+        );
+    }
+    assert_eq!(
+        manifest["dependencies"]["with.dot"]["features"],
+        toml::Value::Array(vec!["a\"quoted\"".into(), "back\\slash".into()])
+    );
+    assert_eq!(
+        manifest["dependencies"]["local"]["path"].as_str(),
+        Some("C:\\crates\\local")
+    );
+}
+
+#[test]
+fn several_versions_of_a_crate() {
+    let config = parse_str(
+        r#"[pkg]
+toolchains = []
+formats = {}
+
+[pkg.crates.base]
+syn = ["1", { version = "2", features = ["full"] }]
+rand = [{ version = "0.7", package = "rand" }, "0.8"]
+"syn-1" = "1"
+empty = []
+nested = [["1"]]
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let crates = &config["pkg"].crates["base"];
+    assert!(matches!(&crates["syn"], Crate::Versions(versions) if versions.len() == 2));
+
+    let mut versioned = crates.clone();
+    versioned.retain(|name, _| name == "syn" || name == "rand");
+    let manifest: toml::Table = toml::from_str(&dependencies(&versioned)).unwrap();
+    let expected: toml::Table = toml::from_str(
+        r#"[dependencies]
+rand = { version = "0.7", package = "rand" }
+rand-1 = { version = "0.8", package = "rand" }
+syn = "1"
+syn-1 = { version = "2", package = "syn", features = ["full"] }
+"#,
+    )
+    .unwrap();
+    assert_eq!(manifest, expected);
+
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: crate `empty` in crate list `base` has an empty list of versions",
+            "pkg: crate `nested` in crate list `base` at index 0 can't be another list",
+            "pkg: crate `syn` in crate list `base` is renamed to `syn-1`, which is also in the crate list",
+        ]
+    );
+}
+
+#[test]
+fn composed_crate_sets() {
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [
+    { channel = "stable", components = [], crate-id = "base", crate-ids = ["web"], exclude-crates = ["openssl"], platforms = [], targets = [], format-map = {} },
+    { channel = "stable", components = [], crate-ids = ["base", "embedded", "missing"], exclude-crates = ["nothing"], platforms = [], targets = [], format-map = {} },
+    { channel = "stable", components = [], platforms = [], targets = [], format-map = {} },
+]
+formats = {}
+
+[pkg.crates.base]
+serde = "1"
+openssl = "0.10"
+
+[pkg.crates.web]
+serde = "1"
+axum = "0.7"
+
+[pkg.crates.embedded]
+serde = { version = "1", default-features = false }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let pkg = &config["pkg"];
+    let mut names: Vec<String> = pkg
+        .crates_for(&pkg.toolchains[0], &HashMap::new())
+        .into_keys()
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["axum", "serde"]);
+    assert_eq!(
+        pkg.toolchains[1].all_crate_ids(),
+        vec!["base", "embedded", "missing"]
+    );
+
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg.toolchains[1]: crate `serde` is in crate lists `base` and `embedded`, which disagree on it",
+            "pkg.toolchains[1]: crate-id `missing` isn't in `crates`",
+            "pkg.toolchains[1]: excludes crate `nothing`, which isn't in any of its crate lists",
+            "pkg.toolchains[2]: needs a `crate-id` or `crate-ids`",
+        ]
+    );
+}
+
+#[test]
+fn platform_specific_crates() {
+    let crates: HashMap<String, Crate> = toml::from_str(
+        r#"
+serde = "1"
+cortex-m = { version = "0.7", target = "cfg(target_arch = \"arm\")" }
+windows-sys = { version = "0.59", features = ["Win32_Foundation"], target = "cfg(windows)" }
+winapi = { version = "0.3", target = "x86_64-pc-windows-msvc" }
+embedded-hal = [{ version = "0.2", target = "cfg(target_arch = \"arm\")" }, "1"]
+"#,
+    )
+    .unwrap();
+    let generated = dependencies(&crates);
+    assert!(generated.starts_with("[dependencies]\n"));
+    let manifest: toml::Table = toml::from_str(&generated).unwrap();
+    let expected: toml::Table = toml::from_str(
+        r#"[dependencies]
+embedded-hal-1 = { version = "1", package = "embedded-hal" }
+serde = "1"
+
+[target.'cfg(target_arch = "arm")'.dependencies]
+cortex-m = { version = "0.7" }
+embedded-hal = { version = "0.2" }
+
+[target.'cfg(windows)'.dependencies]
+windows-sys = { version = "0.59", features = ["Win32_Foundation"] }
+
+[target.x86_64-pc-windows-msvc.dependencies]
+winapi = { version = "0.3" }
+"#,
+    )
+    .unwrap();
+    assert_eq!(manifest, expected);
+}
+
+#[test]
+fn crates_follow_targets() {
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [{ channel = "stable", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = ["thumbv7em-none-eabihf"], format-map = { x86_64-unknown-linux-gnu = "linux" } }]
+formats = { linux = ["gz"] }
+
+[pkg.crates.base]
+serde = "1"
+winapi = { version = "0.3", target = "x86_64-pc-windows-msvc" }
+cortex-m = { version = "0.7", target = "thumbv7em-none-eabihf" }
+windows-sys = { version = "0.59", target = "cfg(windows)" }
+libc = { version = "0.2", target = "cfg(all(unix, not(target_os = \"macos\")))" }
+embedded-hal = [{ version = "0.2", target = "cfg(target_os = \"none\")" }, { version = "1", target = "cfg(windows)" }]
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let pkg = &config["pkg"];
+    let cfgs = HashMap::from([
+        (
+            "x86_64-unknown-linux-gnu".to_string(),
+            "unix\ntarget_os=\"linux\"\ntarget_arch=\"x86_64\"\n"
+                .parse::<cfg::TargetCfg>()
+                .unwrap(),
+        ),
+        (
+            "thumbv7em-none-eabihf".to_string(),
+            "target_os=\"none\"\ntarget_arch=\"arm\"\n"
+                .parse::<cfg::TargetCfg>()
+                .unwrap(),
+        ),
+    ]);
+    let crates = pkg.crates_for(&pkg.toolchains[0], &cfgs);
+    let mut names: Vec<&String> = crates.keys().collect();
+    names.sort();
+    assert_eq!(names, vec!["cortex-m", "embedded-hal", "libc", "serde"]);
+    assert!(matches!(&crates["embedded-hal"], Crate::Versions(versions) if versions.len() == 1));
+
+    // without the cfg values, cfg(...) targets are kept
+    let crates = pkg.crates_for(&pkg.toolchains[0], &HashMap::new());
+    assert!(crates.contains_key("windows-sys"));
+    assert!(!crates.contains_key("winapi"));
+}
+
+#[test]
+fn cfg_expressions() {
+    let cfg: cfg::TargetCfg = "unix\ntarget_os=\"linux\"\ntarget_pointer_width=\"64\"\n"
+        .parse()
+        .unwrap();
+    for (expr, expected) in [
+        ("cfg(unix)", true),
+        ("cfg(windows)", false),
+        ("cfg(target_os = \"linux\")", true),
+        ("cfg(not(target_os = \"linux\"))", false),
+        ("cfg(all(unix, target_pointer_width = \"64\"))", true),
+        ("cfg(any(windows, target_os = \"macos\"))", false),
+        ("cfg(all())", true),
+        ("cfg(any())", false),
+    ] {
+        let parsed: cfg::CfgExpr = expr.parse().unwrap();
+        assert_eq!(parsed.matches(&cfg), expected, "{expr}");
+    }
+    for expr in [
+        "windows",
+        "cfg(windows",
+        "cfg(not(a, b))",
+        "cfg(maybe(unix))",
+        "cfg(target_os = linux)",
+        "cfg(unix) extra",
+    ] {
+        assert!(expr.parse::<cfg::CfgExpr>().is_err(), "{expr}");
+    }
+}
+
+#[test]
+fn validate_crate_targets() {
+    let config = parse_str(
+        r#"[pkg]
+toolchains = [{ channel = "stable", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = { x86_64-unknown-linux-gnu = "linux" } }]
+formats = { linux = ["gz"] }
+
+[pkg.crates.base]
+winapi = { version = "0.3", target = "x86_64-pc-windows-msvc" }
+made-up = { version = "1", target = "x86_64-made-up-os" }
+broken = { version = "1", target = "cfg(all(unix)" }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: crate `broken` in crate list `base` has an invalid `target` `cfg(all(unix)`: expected `)`, found the end",
+            "pkg.toolchains[0]: crate `made-up` has an unknown target `x86_64-made-up-os`",
+        ]
+    );
+}
+
+#[test]
+fn patches() {
+    let dir = write_files(
+        "patches",
+        &[
+            (
+                "config/patches.toml",
+                r#"[patches.base]
+log = { path = "../forks/log" }
+serde = { git = "https://example.com/serde.git", branch = "fix" }
+"#,
+            ),
+            ("forks/log/Cargo.toml", "[package]\nname = \"log\"\n"),
+            ("forks/log/src/lib.rs", "// patched\n"),
+            ("forks/log/target/debug/junk", ""),
+            (
+                "config/rust-config.toml",
+                r#"[pkg]
+include = ["patches.toml"]
+toolchains = [{ channel = "stable", components = [], crate-id = "base", platforms = [], targets = [], format-map = {} }]
+formats = {}
+
+[pkg.crates.base]
+serde = "1"
+log = "0.4"
+
+[pkg.patches.missing]
+broken = { version = "1", target = "cfg(windows)" }
+"#,
+            ),
+        ],
+    );
+    let config = parse_file(&dir.join("config/rust-config.toml")).unwrap();
+    let pkg = &config["pkg"];
+    let patches = pkg.patches_for(&pkg.toolchains[0]);
+    assert_eq!(patches.len(), 2);
+
+    // relative paths are relative to the file the patch is in, not to
+    // wherever the generated manifest ends up
+    let fork = dir.join("forks/log").canonicalize().unwrap();
+    let manifest: toml::Table = toml::from_str(&patch::manifest_table(&patches)).unwrap();
+    let expected: toml::Table = toml::from_str(&format!(
+        r#"[patch.crates-io]
+log = {{ path = {} }}
+serde = {{ git = "https://example.com/serde.git", branch = "fix" }}
+"#,
+        toml::Value::String(fork.to_string_lossy().to_string())
+    ))
+    .unwrap();
+    assert_eq!(manifest, expected);
+    assert_eq!(patch::manifest_table(&HashMap::new()), "");
+
+    let readme = patch::readme(&patches);
+    assert!(readme.contains("log = { path = '{?TOOLCHAIN.CRATES_DIR}/patches/log' }\n"));
+
+    let relative = parse_str(
+        "[pkg]\ntoolchains = []\ncrates = {}\nformats = {}\n\n[pkg.patches.base]\nlog = [{ path = \"../forks/log\" }]\n",
+        &dir.join("config/rust-config.toml"),
+    )
+    .unwrap();
+    assert!(matches!(
+        &relative["pkg"].patches["base"]["log"],
+        Crate::Versions(versions) if matches!(
+            &versions[0],
+            Crate::Detailed { path: Some(path), .. } if Path::new(path) == fork
+        )
+    ));
+
+    let local = HashMap::from([("log".to_string(), patches["log"].clone())]);
+    let crates_dir = dir.join("crates");
+    patch::vendor(&local, &crates_dir, true).unwrap();
+    assert_eq!(
+        fs::read_to_string(crates_dir.join("patches/log/src/lib.rs")).unwrap(),
+        "// patched\n"
+    );
+    assert!(!crates_dir.join("patches/log/target").exists());
+
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: patch list `missing` isn't for any crate list in `crates`",
+            "pkg: patch `broken` in patch list `missing` can't have a `target`",
+        ]
+    );
+}
+
+#[test]
+fn git_patches_in_workspaces() {
+    // the fork is a virtual workspace with the crates in subdirectories, like
+    // most repositories with more than one crate
+    let repo = write_files(
+        "patches-workspace",
+        &[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"serde\", \"serde_derive\"]\n",
+            ),
+            ("serde/Cargo.toml", "[package]\nname = \"serde\"\n"),
+            ("serde/src/lib.rs", "// patched serde\n"),
+            (
+                "serde_derive/Cargo.toml",
+                "[package]\nname = \"serde_derive\"\n",
+            ),
+            ("serde_derive/src/lib.rs", "// patched serde_derive\n"),
+            ("test_suite/Cargo.toml", "not toml"),
+        ],
+    );
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .arg("-C")
+            .arg(&repo)
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "-q"]);
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "fork"]);
+
+    let url = repo.to_string_lossy().to_string();
+    let git_patch = |package: Option<&str>| Crate::Detailed {
+        version: None,
+        versions: None,
+        latest_n: None,
+        features: None,
+        default_features: None,
+        optional: None,
+        package: package.map(String::from),
+        registry: None,
+        path: None,
+        git: Some(url.clone()),
+        branch: None,
+        tag: None,
+        rev: None,
+        target: None,
+    };
+    let patches = HashMap::from([
+        ("serde".to_string(), git_patch(None)),
+        ("derive".to_string(), git_patch(Some("serde_derive"))),
+    ]);
+    let crates_dir = repo.join("crates");
+    patch::vendor(&patches, &crates_dir, true).unwrap();
+    assert_eq!(
+        fs::read_to_string(crates_dir.join("patches/serde/Cargo.toml")).unwrap(),
+        "[package]\nname = \"serde\"\n"
+    );
+    assert_eq!(
+        fs::read_to_string(crates_dir.join("patches/derive/src/lib.rs")).unwrap(),
+        "// patched serde_derive\n"
+    );
+    let mut vendored: Vec<_> = fs::read_dir(crates_dir.join("patches"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    vendored.sort();
+    assert_eq!(vendored, ["derive", "serde"]);
+
+    let missing = HashMap::from([("missing".to_string(), git_patch(None))]);
+    assert_eq!(
+        patch::vendor(&missing, &crates_dir, true)
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "patch `missing` from {} doesn't have a package named `missing`",
+            url
+        )
+    );
+    fs::remove_dir_all(repo).unwrap();
+}
+
+#[test]
+fn version_ranges() {
+    assert_eq!(versions::index_path("a"), "1/a");
+    assert_eq!(versions::index_path("cc"), "2/cc");
+    assert_eq!(versions::index_path("log"), "3/l/log");
+    assert_eq!(versions::index_path("Serde"), "se/rd/serde");
+
+    let index = versions::parse_index(
+        r#"{"name":"serde","vers":"0.9.0","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"serde","vers":"1.0.0","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"serde","vers":"1.0.1","deps":[],"cksum":"","features":{},"yanked":true}
+{"name":"serde","vers":"1.0.2","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"serde","vers":"1.1.0-beta.1","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"serde","vers":"1.1.0","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"serde","vers":"2.0.0","deps":[],"cksum":"","features":{},"yanked":false}
+"#,
+    )
+    .unwrap();
+    let req = semver::VersionReq::parse(">=1.0, <2").unwrap();
+    let all: Vec<String> = versions::matching(&index, &req, None)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(all, vec!["1.1.0", "1.0.2", "1.0.0"]);
+    assert_eq!(versions::matching(&index, &req, Some(2)).len(), 2);
+
+    let mut crates: HashMap<String, Crate> = toml::from_str(
+        r#"
+serde = { versions = ">=1.0, <2", latest-n = 2, features = ["derive"] }
+json = ["1", { package = "serde", versions = "^1.0.0" }]
+log = "0.4"
+"#,
+    )
+    .unwrap();
+    let mut fetched = Vec::new();
+    let older = versions::resolve(&mut crates, |name| {
+        fetched.push(name.to_string());
+        Ok(index.clone())
+    })
+    .unwrap();
+    assert_eq!(fetched, vec!["serde", "serde"]);
+    assert_eq!(
+        crates["serde"].to_toml().to_string(),
+        "{ version = \"=1.1.0\", features = [\"derive\"] }"
+    );
+    assert_eq!(
+        crates["json"].to_toml().to_string(),
+        "[\"1\", { version = \"=1.1.0\", package = \"serde\" }]"
+    );
+    let older: Vec<(String, String)> = older
+        .iter()
+        .map(|(name, krate)| (name.clone(), krate.to_toml().to_string()))
+        .collect();
+    assert_eq!(
+        older,
+        vec![
+            (
+                "json".to_string(),
+                "{ version = \"=1.0.2\", package = \"serde\" }".to_string()
+            ),
+            (
+                "json".to_string(),
+                "{ version = \"=1.0.0\", package = \"serde\" }".to_string()
+            ),
+            (
+                "serde".to_string(),
+                "{ version = \"=1.0.2\", features = [\"derive\"] }".to_string()
+            ),
+        ]
+    );
+    let manifest: toml::Table = toml::from_str(&versions::manifest(
+        "serde",
+        &Crate::Version("=1.0.2".to_string()),
+    ))
+    .unwrap();
+    assert_eq!(manifest["dependencies"]["serde"].as_str(), Some("=1.0.2"));
+    assert!(manifest.contains_key("workspace"));
+
+    let mut unresolved: HashMap<String, Crate> = toml::from_str(
+        "serde = { versions = \">=1.0, <2\", latest-n = 2 }\njson = [\"1\", { package = \"serde\", versions = \"^1.0.0\" }]\n",
+    )
+    .unwrap();
+    versions::keep_ranges(&mut unresolved);
+    assert_eq!(
+        unresolved["serde"].to_toml().to_string(),
+        "{ version = \">=1.0, <2\" }"
+    );
+    assert_eq!(
+        unresolved["json"].to_toml().to_string(),
+        "[\"1\", { version = \"^1.0.0\", package = \"serde\" }]"
+    );
+
+    let mut missing: HashMap<String, Crate> =
+        toml::from_str("serde = { versions = \">=3\" }").unwrap();
+    let err = versions::resolve(&mut missing, |_| Ok(index.clone())).unwrap_err();
+    assert_eq!(err.to_string(), "no version of `serde` matches `>=3`");
+
+    let config = parse_str(
+        r#"[pkg]
+toolchains = []
+formats = {}
+
+[pkg.crates.base]
+both = { version = "1", versions = ">=1" }
+invalid = { versions = "one" }
+local = { versions = "1", path = "../local" }
+lonely = { version = "1", latest-n = 0 }
+"#,
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let errors: Vec<String> = validate::validate(&config)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "pkg: crate `both` in crate list `base` can't have both a `version` and `versions`",
+            "pkg: crate `invalid` in crate list `base` has an invalid `versions` `one`: unexpected character 'o' while parsing major version number",
+            "pkg: crate `local` in crate list `base` can only use `versions` with crates from crates.io",
+            "pkg: crate `lonely` in crate list `base` has a `latest-n` but no `versions`",
+            "pkg: crate `lonely` in crate list `base` has a `latest-n` of 0",
+        ]
+    );
+}
+
+#[test]
+fn json_schema() {
+    let schema: serde_json::Value = serde_json::from_str(&schema::to_json_string()).unwrap();
+    let definitions = &schema["definitions"];
+    assert_eq!(
+        schema["additionalProperties"]["$ref"],
+        "#/definitions/RustConfigInner"
+    );
+    assert_eq!(
+        schema["properties"]["defaults"]["properties"]["toolchain"]["$ref"],
+        "#/definitions/Toolchain"
+    );
+    // inheritance can fill in anything, so nothing is required
+    assert!(definitions["Toolchain"].get("required").is_none());
+    assert!(definitions["RustConfigInner"].get("required").is_none());
+    // TOML doesn't have null
+    assert!(!schema::to_json_string().contains("\"null\""));
+
+    // everything in the example config is allowed by the schema
+    let toolchain = &definitions["Toolchain"]["properties"];
+    let contains = |values: &serde_json::Value, value: &str| {
+        values.as_array().unwrap().iter().any(|v| v == value)
+    };
+    let example: toml::Table =
+        toml::from_str(&fs::read_to_string("rust-config.toml").unwrap()).unwrap();
+    for package in example.values() {
+        for (key, _) in package.as_table().unwrap() {
+            assert!(definitions["RustConfigInner"]["properties"]
+                .get(key)
+                .is_some());
+        }
+        for item in package["toolchains"].as_array().unwrap() {
+            for (key, value) in item.as_table().unwrap() {
+                assert!(toolchain.get(key).is_some(), "unknown key {key}");
+                if key == "platforms" || key == "targets" {
+                    for target in value.as_array().unwrap() {
+                        let enum_values = &toolchain[key]["items"]["anyOf"][0]["enum"];
+                        assert!(contains(enum_values, target.as_str().unwrap()));
+                    }
+                }
+            }
+            let channel = item["channel"].as_str().unwrap();
+            assert!(contains(&toolchain["channel"]["anyOf"][0]["enum"], channel));
+        }
+        for formats in package["formats"].as_table().unwrap().values() {
+            for format in formats.as_array().unwrap() {
+                let format = format.as_str().unwrap();
+                assert!(contains(&definitions["Format"]["enum"], format));
+            }
+        }
+    }
+    assert!(!contains(&definitions["Format"]["enum"], "zip"));
+    assert!(contains(
+        &toolchain["targets"]["items"]["anyOf"][0]["enum"],
+        "tier1"
+    ));
+    // targets newer than `targets::TARGETS` are allowed too
+    let triple = toolchain["targets"]["items"]["anyOf"][1]["pattern"]
+        .as_str()
+        .unwrap();
+    let triple = regex::Regex::new(triple).unwrap();
+    for target in targets::TARGETS {
+        assert!(triple.is_match(target), "{target}");
+    }
+    assert!(triple.is_match("x86_64-unknown-linux-gnux32"));
+    assert!(triple.is_match("avr-none"));
+    assert!(!triple.is_match("linux"));
+    assert!(!triple.is_match("x86_64 unknown linux gnu"));
+}
+
+#[test]
+fn selecting_packages() {
+    let toolchain = |channel: &str| {
+        format!(
+            r#"{{ channel = "{}", components = [], crate-id = "base", platforms = ["x86_64-unknown-linux-gnu"], targets = [], format-map = {{}} }}"#,
+            channel
+        )
+    };
+    let config = parse_str(
+        &format!(
+            r#"[web-api]
+toolchains = [{stable}, {nightly}]
+crates = {{ base = {{}} }}
+formats = {{}}
+
+[web-ui]
+toolchains = [{stable}]
+crates = {{ base = {{}} }}
+formats = {{}}
+
+[embedded]
+toolchains = [{nightly}, {beta}]
+crates = {{ base = {{}} }}
+formats = {{}}
+"#,
+            stable = toolchain("stable"),
+            nightly = toolchain("nightly"),
+            beta = toolchain("beta"),
+        ),
+        Path::new("rust-config.toml"),
+    )
+    .unwrap();
+    let select = |packages: &[&str], toolchains: &[&str]| {
+        select::select(
+            config.clone(),
+            &select::Selection {
+                packages: packages.iter().map(ToString::to_string).collect(),
+                toolchains: toolchains.iter().map(ToString::to_string).collect(),
+            },
+        )
+        .map(|selected| {
+            selected
+                .into_iter()
+                .map(|selected| (selected.name, selected.toolchains))
+                .collect::<Vec<_>>()
+        })
+        .map_err(|err| err.to_string())
+    };
+
+    assert_eq!(
+        select(&[], &[]),
+        Ok(vec![
+            ("embedded".to_string(), vec![0, 1]),
+            ("web-api".to_string(), vec![0, 1]),
+            ("web-ui".to_string(), vec![0]),
+        ])
+    );
+    assert_eq!(
+        select(&["web-*"], &["nightly"]),
+        Ok(vec![("web-api".to_string(), vec![1])])
+    );
+    assert_eq!(
+        select(&["embedded", "web-ui"], &["1"]),
+        Ok(vec![("embedded".to_string(), vec![1])])
+    );
+    assert_eq!(
+        select(&["web-*", "mobile"], &[]),
+        Err("no package matches `mobile`".to_string())
+    );
+    assert_eq!(
+        select(&["web-ui"], &["beta"]),
+        Err("no selected toolchain matches `beta`".to_string())
+    );
+
+    let selected = select::select(
+        config.clone(),
+        &select::Selection {
+            packages: vec!["web-api".to_string()],
+            toolchains: vec!["1".to_string()],
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        select::list(&selected),
+        "web-api\n  [1] nightly (crates: base; platforms: x86_64-unknown-linux-gnu; targets: none)\n"
+    );
 }
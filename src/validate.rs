@@ -0,0 +1,419 @@
+//! Semantic validation of a [`RustConfig`].
+//!
+//! [`parse_file`](crate::parse_file) only checks that a config has the right
+//! shape. The checks in here catch the mistakes that would otherwise only
+//! show up once [`download_all`](crate::copied::download_all) runs (or as a
+//! panic in the CLI), and they report every problem at once without touching
+//! the network. Platforms and targets(including the target triples of the
+//! crates a toolchain gets) are checked against [`targets::TARGETS`], unless
+//! the targets of a channel are known(see [`validate_with_targets`]).
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    cfg::CfgExpr,
+    channel::{ToolchainSpec, PROFILES},
+    targets, Crate, FormatKind, RustConfig, RustConfigInner, Suffix, Toolchain,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single problem found by [`validate`].
+pub struct ValidationError {
+    /// The name of the package(the key in the [`RustConfig`]) the problem is in.
+    pub package: String,
+    /// The index of the toolchain the problem is in, if it's in one.
+    pub toolchain: Option<usize>,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.toolchain {
+            Some(idx) => write!(f, "{}.toolchains[{idx}]: {}", self.package, self.message),
+            None => write!(f, "{}: {}", self.package, self.message),
+        }
+    }
+}
+
+/// Checks a whole [`RustConfig`] and returns every problem found, sorted by
+/// package name. An empty list means the config is valid.
+pub fn validate(config: &RustConfig) -> Vec<ValidationError> {
+    validate_with_targets(config, &HashMap::new())
+}
+
+/// Like [`validate`], but the platforms and targets of toolchains whose
+/// channel is in `known_targets` are checked against the targets listed for
+/// it there(usually the ones `rust-std` is available for in its channel
+/// manifest) instead of [`targets::TARGETS`].
+pub fn validate_with_targets(
+    config: &RustConfig,
+    known_targets: &HashMap<String, Vec<String>>,
+) -> Vec<ValidationError> {
+    let mut names: Vec<&String> = config.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .flat_map(|name| validate_package_with_targets(name, &config[name], known_targets))
+        .collect()
+}
+
+/// Checks a single package. See [`validate`].
+pub fn validate_package(name: &str, cfg: &RustConfigInner) -> Vec<ValidationError> {
+    validate_package_with_targets(name, cfg, &HashMap::new())
+}
+
+/// Checks a single package. See [`validate_with_targets`].
+pub fn validate_package_with_targets(
+    name: &str,
+    cfg: &RustConfigInner,
+    known_targets: &HashMap<String, Vec<String>>,
+) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut push = |toolchain: Option<usize>, message: String| {
+        errors.push(ValidationError {
+            package: name.to_string(),
+            toolchain,
+            message,
+        })
+    };
+
+    let mut format_ids: Vec<&String> = cfg.formats.keys().collect();
+    format_ids.sort();
+    for id in format_ids {
+        let formats = &cfg.formats[id];
+        if formats.is_empty() {
+            push(None, format!("format list `{id}` is empty"));
+        }
+        for (idx, format) in formats.iter().enumerate() {
+            if idx > 0 && format.format.is_installer() {
+                push(
+                    None,
+                    format!(
+                        "format list `{id}` can only contain `{}` as its first element",
+                        format.format
+                    ),
+                );
+            }
+            if idx > 0 && format.suffix == Suffix::Only {
+                push(
+                    None,
+                    format!("format list `{id}` can only have a suffix on its first element"),
+                );
+            }
+        }
+    }
+
+    let mut crate_ids: Vec<&String> = cfg.crates.keys().collect();
+    crate_ids.sort();
+    for id in crate_ids {
+        let mut names: Vec<&String> = cfg.crates[id].keys().collect();
+        names.sort();
+        for name in names {
+            let krate = &cfg.crates[id][name];
+            for message in validate_crate(krate) {
+                push(
+                    None,
+                    format!("crate `{name}` in crate list `{id}` {message}"),
+                );
+            }
+            for (renamed, _) in krate.lower(name).iter().skip(1) {
+                if cfg.crates[id].contains_key(renamed) {
+                    push(
+                        None,
+                        format!(
+                            "crate `{name}` in crate list `{id}` is renamed to `{renamed}`, which is also in the crate list"
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut patch_ids: Vec<&String> = cfg.patches.keys().collect();
+    patch_ids.sort();
+    for id in patch_ids {
+        if !cfg.crates.contains_key(id) {
+            push(
+                None,
+                format!("patch list `{id}` isn't for any crate list in `crates`"),
+            );
+        }
+        let mut names: Vec<&String> = cfg.patches[id].keys().collect();
+        names.sort();
+        for name in names {
+            for message in validate_patch(&cfg.patches[id][name]) {
+                push(
+                    None,
+                    format!("patch `{name}` in patch list `{id}` {message}"),
+                );
+            }
+        }
+    }
+
+    for (idx, toolchain) in cfg.toolchains.iter().enumerate() {
+        let known: Vec<&str> = match known_targets.get(&toolchain.channel) {
+            Some(known) => known.iter().map(String::as_str).collect(),
+            None => targets::TARGETS.to_vec(),
+        };
+        for message in validate_toolchain(cfg, toolchain, &known) {
+            push(Some(idx), message);
+        }
+    }
+
+    errors
+}
+
+fn validate_crate(krate: &Crate) -> Vec<String> {
+    let mut errors = Vec::new();
+    if let Crate::Versions(versions) = krate {
+        if versions.is_empty() {
+            errors.push("has an empty list of versions".to_string());
+        }
+        for (idx, krate) in versions.iter().enumerate() {
+            if let Crate::Versions(_) = krate {
+                errors.push(format!("at index {idx} can't be another list"));
+                continue;
+            }
+            for message in validate_crate(krate) {
+                errors.push(format!("at index {idx} {message}"));
+            }
+        }
+        return errors;
+    }
+    let Crate::Detailed {
+        version,
+        versions,
+        latest_n,
+        registry,
+        path,
+        git,
+        branch,
+        tag,
+        rev,
+        target,
+        ..
+    } = krate
+    else {
+        return errors;
+    };
+    if version.is_none() && versions.is_none() && path.is_none() && git.is_none() {
+        errors.push("needs a `version`, `versions`, `path`, or `git`".to_string());
+    }
+    if let Some(versions) = versions {
+        if version.is_some() {
+            errors.push("can't have both a `version` and `versions`".to_string());
+        }
+        if let Err(err) = semver::VersionReq::parse(versions) {
+            errors.push(format!("has an invalid `versions` `{versions}`: {err}"));
+        }
+        if registry.is_some() || path.is_some() || git.is_some() {
+            errors.push("can only use `versions` with crates from crates.io".to_string());
+        }
+    } else if latest_n.is_some() {
+        errors.push("has a `latest-n` but no `versions`".to_string());
+    }
+    if *latest_n == Some(0) {
+        errors.push("has a `latest-n` of 0".to_string());
+    }
+    // whether a target triple exists depends on the toolchain, so that's
+    // checked in `validate_toolchain`
+    if let Some(target) = target {
+        if target.starts_with("cfg(") {
+            if let Err(err) = target.parse::<CfgExpr>() {
+                errors.push(format!("has an invalid `target` `{target}`: {err}"));
+            }
+        } else if !target.contains('-') || target.contains(char::is_whitespace) {
+            errors.push(format!(
+                "has an invalid `target` `{target}`(expected `cfg(...)` or a target triple)"
+            ));
+        }
+    }
+    if path.is_some() && git.is_some() {
+        errors.push("can't have both a `path` and a `git`".to_string());
+    }
+    let refs = [("branch", branch), ("tag", tag), ("rev", rev)];
+    let set: Vec<&str> = refs
+        .iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(key, _)| *key)
+        .collect();
+    if git.is_none() {
+        for key in &set {
+            errors.push(format!("has a `{key}` but no `git`"));
+        }
+    } else if set.len() > 1 {
+        errors.push(format!(
+            "can only have one of `branch`, `tag`, and `rev`, but has `{}`",
+            set.join("`, `")
+        ));
+    }
+    errors
+}
+
+/// The [`target`](Crate::Detailed::target)s of `krate` and its entries.
+fn crate_targets(krate: &Crate) -> Vec<&str> {
+    match krate {
+        Crate::Versions(versions) => versions.iter().flat_map(crate_targets).collect(),
+        Crate::Detailed {
+            target: Some(target),
+            ..
+        } => vec![target.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+/// Checks a [patch](crate::patch), which on top of being a valid crate has to
+/// have a `path` or `git` and no `target`.
+fn validate_patch(krate: &Crate) -> Vec<String> {
+    let mut errors = validate_crate(krate);
+    let patches = match krate {
+        Crate::Versions(versions) => versions.iter().collect(),
+        krate => vec![krate],
+    };
+    for patch in patches {
+        match patch {
+            Crate::Detailed {
+                target: Some(_), ..
+            } => errors.push("can't have a `target`".to_string()),
+            Crate::Detailed { path: Some(_), .. } | Crate::Detailed { git: Some(_), .. } => {}
+            Crate::Versions(_) => {}
+            _ => errors.push("needs a `path` or `git`".to_string()),
+        }
+    }
+    errors
+}
+
+fn validate_toolchain(cfg: &RustConfigInner, toolchain: &Toolchain, known: &[&str]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if let Err(err) = toolchain.channel.parse::<ToolchainSpec>() {
+        errors.push(format!("unknown channel `{}`: {}", toolchain.channel, err));
+    }
+    if let Some(profile) = &toolchain.profile {
+        if !PROFILES.contains(&profile.as_str()) {
+            errors.push(format!(
+                "unknown profile `{profile}`(expected `minimal`, `default`, or `complete`)"
+            ));
+        }
+    }
+    let crate_ids = toolchain.all_crate_ids();
+    if crate_ids.is_empty() {
+        errors.push("needs a `crate-id` or `crate-ids`".to_string());
+    }
+    // maps each crate to the first crate list it's in
+    let mut seen: HashMap<&str, (&str, &Crate)> = HashMap::new();
+    for id in crate_ids {
+        let Some(crates) = cfg.crates.get(id) else {
+            errors.push(format!("crate-id `{id}` isn't in `crates`"));
+            continue;
+        };
+        let mut names: Vec<&String> = crates.keys().collect();
+        names.sort();
+        for name in names {
+            if toolchain.exclude_crates.contains(name) {
+                continue;
+            }
+            match seen.get(name.as_str()) {
+                Some((first, krate)) if *krate != &crates[name] => errors.push(format!(
+                    "crate `{name}` is in crate lists `{first}` and `{id}`, which disagree on it"
+                )),
+                Some(_) => {}
+                None => {
+                    seen.insert(name, (id, &crates[name]));
+                }
+            }
+        }
+    }
+    let mut names: Vec<&&str> = seen.keys().collect();
+    names.sort();
+    for name in names {
+        for target in crate_targets(seen[name].1) {
+            if !target.starts_with("cfg(") && !known.contains(&target) {
+                errors.push(format!("crate `{name}` has an unknown target `{target}`"));
+            }
+        }
+    }
+    for name in &toolchain.exclude_crates {
+        let found = toolchain.all_crate_ids().into_iter().any(|id| {
+            cfg.crates
+                .get(id)
+                .is_some_and(|crates| crates.contains_key(name))
+        });
+        if !found {
+            errors.push(format!(
+                "excludes crate `{name}`, which isn't in any of its crate lists"
+            ));
+        }
+    }
+    // patterns are expanded against the known targets too, since the
+    // manifest isn't necessarily downloaded yet
+    let mut platforms = Vec::new();
+    for platform in &toolchain.platforms {
+        match targets::expand(std::slice::from_ref(platform), known) {
+            Ok(expanded) => {
+                if !targets::is_pattern(platform) && !known.contains(&platform.as_str()) {
+                    errors.push(format!("unknown platform `{platform}`"));
+                }
+                for platform in expanded {
+                    if !platforms.contains(&platform) {
+                        platforms.push(platform);
+                    }
+                }
+            }
+            Err(err) => errors.push(err),
+        }
+    }
+    for target in &toolchain.targets {
+        match targets::expand(std::slice::from_ref(target), known) {
+            Err(err) => errors.push(err),
+            Ok(_) if !targets::is_pattern(target) && !known.contains(&target.as_str()) => {
+                errors.push(format!("unknown target `{target}`"))
+            }
+            Ok(_) => {}
+        }
+    }
+
+    let mut keys: Vec<&String> = toolchain.format_map.keys().collect();
+    keys.sort();
+    for key in keys {
+        let id = &toolchain.format_map[key];
+        if !platforms
+            .iter()
+            .any(|platform| targets::matches(key, platform))
+        {
+            errors.push(format!(
+                "format-map contains `{key}`, which isn't in `platforms`"
+            ));
+        }
+        if !cfg.formats.contains_key(id) {
+            errors.push(format!(
+                "format-map refers to format list `{id}`, which isn't in `formats`"
+            ));
+        }
+    }
+    for platform in &platforms {
+        let Some(id) = toolchain.format_for(platform) else {
+            errors.push(format!("platform `{platform}` has no entry in format-map"));
+            continue;
+        };
+        let Some(first) = cfg.formats.get(id).and_then(|formats| formats.first()) else {
+            continue;
+        };
+        if first.suffix != Suffix::Only {
+            continue;
+        }
+        if first.format == FormatKind::Msi && !platform.contains("windows") {
+            errors.push(format!(
+                "platform `{platform}` is not windows but format list `{id}` requires msi"
+            ));
+        }
+        if first.format == FormatKind::Pkg && !platform.contains("apple") {
+            errors.push(format!(
+                "platform `{platform}` is not apple but format list `{id}` requires pkg"
+            ));
+        }
+    }
+
+    errors
+}
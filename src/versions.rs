@@ -0,0 +1,186 @@
+//! Vendoring every version of a crate that matches a semver range.
+//!
+//! ```toml
+//! [pkg.crates.base]
+//! serde = { versions = ">=1.0, <2", latest-n = 5, features = ["derive"] }
+//! ```
+//!
+//! A crate with `versions` is [resolved](resolve) against the crates.io index
+//! into every non-yanked version matching the range(or only the newest
+//! `latest-n` of them). The newest one is written to the generated
+//! `Cargo.toml` like any other crate. Cargo can't have two semver-compatible
+//! versions of a crate in one lockfile, so every other version gets a
+//! [manifest] of its own, and the local registry is synced with each of their
+//! lockfiles in turn, along with their dependencies.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Error};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+
+use crate::{dependencies, Crate};
+
+/// The sparse index of crates.io.
+pub const CRATES_IO_INDEX: &str = "https://index.crates.io/";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+/// A single version of a crate, from a line of its index file.
+pub struct IndexVersion {
+    /// The version.
+    pub vers: String,
+    /// Whether the version is yanked.
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// The path of the index file of the crate `name`, relative to the root of the
+/// index, like `se/rd/serde` or `3/l/log`.
+pub fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/// Parses the contents of an index file, which has one JSON object per line.
+pub fn parse_index(contents: &str) -> Result<Vec<IndexVersion>, Error> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Downloads and parses the index file of the crate `name` from the sparse
+/// index at `index_url`.
+pub fn fetch_index(index_url: &str, name: &str) -> Result<Vec<IndexVersion>, Error> {
+    let url = format!("{}{}", index_url, index_path(name));
+    let response = reqwest::blocking::get(&url)?;
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "failed to download the index of `{}`: {}",
+            name,
+            response.status()
+        ));
+    }
+    parse_index(&response.text()?)
+}
+
+/// The versions in `index` that match `req` and aren't yanked, newest first.
+/// If `latest_n` is set, only that many are returned.
+pub fn matching(index: &[IndexVersion], req: &VersionReq, latest_n: Option<usize>) -> Vec<Version> {
+    let mut versions: Vec<Version> = index
+        .iter()
+        .filter(|version| !version.yanked)
+        .filter_map(|version| Version::parse(&version.vers).ok())
+        .filter(|version| req.matches(version))
+        .collect();
+    versions.sort_by(|a, b| b.cmp(a));
+    versions.dedup();
+    if let Some(latest_n) = latest_n {
+        versions.truncate(latest_n);
+    }
+    versions
+}
+
+/// Resolves every crate in `crates` that has
+/// [`versions`](Crate::Detailed::versions), in place, with `index`, which
+/// returns the index of a crate given its name(like [`fetch_index`] does).
+///
+/// Each one is pinned to the newest matching version. Every other matching
+/// version is returned, pinned and keyed by the crate's name, so it can be
+/// given a [manifest] of its own.
+pub fn resolve(
+    crates: &mut HashMap<String, Crate>,
+    mut index: impl FnMut(&str) -> Result<Vec<IndexVersion>, Error>,
+) -> Result<Vec<(String, Crate)>, Error> {
+    let mut rest = Vec::new();
+    let mut names: Vec<String> = crates.keys().cloned().collect();
+    names.sort();
+    for name in names {
+        let krate = crates.get_mut(&name).unwrap();
+        match krate {
+            Crate::Versions(versions) => {
+                for krate in versions {
+                    pin(&name, krate, &mut index, &mut rest)?;
+                }
+            }
+            krate => pin(&name, krate, &mut index, &mut rest)?,
+        }
+    }
+    Ok(rest)
+}
+
+/// Replaces the [`versions`](Crate::Detailed::versions) of every crate in
+/// `crates` with a `version` requirement of the same range, without looking
+/// anything up. Used instead of [`resolve`] when the crates aren't built, so
+/// the generated `Cargo.toml` is still valid.
+pub fn keep_ranges(crates: &mut HashMap<String, Crate>) {
+    for krate in crates.values_mut() {
+        let entries: Vec<&mut Crate> = match krate {
+            Crate::Versions(versions) => versions.iter_mut().collect(),
+            krate => vec![krate],
+        };
+        for entry in entries {
+            if let Crate::Detailed {
+                version,
+                versions: versions @ Some(_),
+                latest_n,
+                ..
+            } = entry
+            {
+                *version = versions.take();
+                *latest_n = None;
+            }
+        }
+    }
+}
+
+/// Pins a single crate. See [`resolve`].
+fn pin(
+    name: &str,
+    krate: &mut Crate,
+    index: &mut impl FnMut(&str) -> Result<Vec<IndexVersion>, Error>,
+    rest: &mut Vec<(String, Crate)>,
+) -> Result<(), Error> {
+    let Crate::Detailed {
+        version,
+        versions: versions @ Some(_),
+        latest_n,
+        package,
+        ..
+    } = krate
+    else {
+        return Ok(());
+    };
+    let range = versions.take().unwrap();
+    let req = VersionReq::parse(&range)
+        .map_err(|err| anyhow!("invalid `versions` `{}` for `{}`: {}", range, name, err))?;
+    let package = package.clone().unwrap_or_else(|| name.to_string());
+    let matching = matching(&index(&package)?, &req, latest_n.take());
+    let Some((newest, older)) = matching.split_first() else {
+        return Err(anyhow!("no version of `{}` matches `{}`", package, range));
+    };
+    *version = Some(format!("={}", newest));
+    for older in older {
+        let mut krate = krate.clone();
+        if let Crate::Detailed { version, .. } = &mut krate {
+            *version = Some(format!("={}", older));
+        }
+        rest.push((name.to_string(), krate));
+    }
+    Ok(())
+}
+
+/// The Cargo.toml of a project that only depends on `krate`, under the name
+/// `name`. Used to vendor a version returned by [`resolve`].
+pub fn manifest(name: &str, krate: &Crate) -> String {
+    format!(
+        "[package]\nname = \"download-version\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[workspace]\n\n{}",
+        dependencies(&HashMap::from([(name.to_string(), krate.clone())]))
+    )
+}